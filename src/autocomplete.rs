@@ -1,77 +1,381 @@
-use std::{ops::Range, ptr::NonNull, sync::Arc};
+use std::{cell::OnceCell, collections::HashMap, ops::Range, sync::Arc};
 
 use byteyarn::yarn;
-use egui::{text::{CCursor, LayoutJob}, text_selection::CCursorRange, Align, Color32, FontSelection, Sense, Ui, Vec2};
+use egui::{text::{CCursor, LayoutJob}, text_selection::CCursorRange, Align, Color32, FontSelection, Key, Modifiers, RichText, ScrollArea, Sense, Ui, Vec2};
 use vince621_core::{db::tags::{Tag, TagAndImplicationDatabase, TagCategory}, search::e6_posts::parse_query_for_autocomplete};
 
+use crate::{fuzzy, highlight::METATAG_COLOR, metatags, text_offsets, usage::TagUsage};
+
 const MAX_AUTOCOMPLETION_COUNT: usize = 20;
 
+/// How many prefix matches to pull out of the database before splitting them up by category.
+/// Needs to be well over `MAX_AUTOCOMPLETION_COUNT` so the smaller categories aren't crowded out
+/// by general tags, and so the user's favorite tags have something to be promoted out of.
+const CANDIDATE_POOL: usize = 200;
+
+/// Prefixes that restrict suggestions to one category, e.g. `artist:foo`.  The prefix gets
+/// dropped when a suggestion is accepted.
+const CATEGORY_PREFIXES: [(&str, TagCategory); 7] = [
+    ("artist", TagCategory::Artist),
+    ("copyright", TagCategory::Copyright),
+    ("character", TagCategory::Character),
+    ("species", TagCategory::Species),
+    ("general", TagCategory::General),
+    ("meta", TagCategory::Meta),
+    ("lore", TagCategory::Lore),
+];
+
+/// Don't bother with fuzzy matching until the user has typed at least this many characters --
+/// everything is within one typo of a two letter prefix.
+const MIN_FUZZY_QUERY_LEN: usize = 3;
+
+/// Most tags the fuzzy fallback will score per keystroke.  This runs on the UI thread, so it needs
+/// a bound; candidates are scanned most popular first, so it's the obscure ones that get skipped.
+const FUZZY_SCAN_LIMIT: usize = 20_000;
+
+/// Indices into `tags()` of every tag with posts, keyed by each of its first two characters and
+/// sorted most popular first.  The entry for a query's first character is exactly the set that
+/// the fuzzy matcher's first-letter pre-filter lets through.
+fn build_fuzzy_index(tag_db: &TagAndImplicationDatabase) -> HashMap<char, Vec<usize>> {
+    let tags = tag_db.tags();
+    let mut index: HashMap<char, Vec<usize>> = HashMap::new();
+    for (i, tag) in tags.iter().enumerate() {
+        if tag.post_count == 0 {
+            continue;
+        }
+        let mut chars = tag.name.as_str().chars();
+        let first = chars.next();
+        let second = chars.next().filter(|c| Some(*c) != first);
+        for c in first.into_iter().chain(second) {
+            index.entry(c).or_default().push(i);
+        }
+    }
+    for bucket in index.values_mut() {
+        bucket.sort_by_key(|i| std::cmp::Reverse(tags[*i].post_count));
+    }
+    index
+}
+
+/// The colors e621 uses for each tag category.
+pub(crate) fn tag_category_color(category: TagCategory) -> Color32 {
+    match category {
+        TagCategory::General => Color32::from_rgb(0xb4,0xc7,0xd9),
+        TagCategory::Artist => Color32::from_rgb(0xf2,0xac,0x08),
+        TagCategory::Copyright => Color32::from_rgb(0xdd,0x00,0xdd),
+        TagCategory::Character => Color32::from_rgb(0x00,0xaa,0x00),
+        TagCategory::Species => Color32::from_rgb(0xed,0x5d,0x1f),
+        TagCategory::Invalid => Color32::from_rgb(0xff,0x3d,0x3d),
+        TagCategory::Meta => Color32::from_rgb(0xff,0xff,0xff),
+        TagCategory::Lore => Color32::from_rgb(0x22,0x88,0x22),
+    }
+}
+
+/// Tag categories in the order e621 lists them on a post page, with a heading for each.
+pub(crate) const CATEGORY_ORDER: [(TagCategory, &str); 8] = [
+    (TagCategory::Artist, "Artists"),
+    (TagCategory::Copyright, "Copyrights"),
+    (TagCategory::Character, "Characters"),
+    (TagCategory::Species, "Species"),
+    (TagCategory::General, "General"),
+    (TagCategory::Meta, "Meta"),
+    (TagCategory::Lore, "Lore"),
+    (TagCategory::Invalid, "Invalid"),
+];
+
+/// Tallest the popup gets before it starts scrolling.
+const POPUP_MAX_HEIGHT: f32 = 400.0;
+
+/// How many related tags to list per section of the details tooltip.
+const MAX_DETAIL_TAGS: usize = 15;
+
+/// Lists a tag's implications and aliases, for the tooltip next to an autocomplete suggestion.
+/// (The local database has no wiki pages, so there's nothing more to say about a tag than this.)
+fn show_tag_details(ui: &mut Ui, tag_db: &TagAndImplicationDatabase, tag: &Tag) {
+    ui.label(RichText::new(tag.name.as_str()).strong().color(tag_category_color(tag.category)));
+    ui.label(format!("{} posts", tag.post_count));
+
+    let section = |ui: &mut Ui, heading: &str, names: Vec<RichText>| {
+        if names.is_empty() {
+            return;
+        }
+        ui.separator();
+        ui.label(RichText::new(heading).small());
+        let total = names.len();
+        ui.horizontal_wrapped(|ui| {
+            for name in names.into_iter().take(MAX_DETAIL_TAGS) {
+                ui.label(name);
+            }
+            if total > MAX_DETAIL_TAGS {
+                ui.label(format!("and {} more", total - MAX_DETAIL_TAGS));
+            }
+        });
+    };
+    let colored = |t: &Tag| RichText::new(t.name.as_str()).color(tag_category_color(t.category));
+    section(ui, "Implies", tag_db.implications(tag.id).map(colored).collect());
+    section(ui, "Implied by", tag_db.implicators(tag.id).map(colored).collect());
+    section(ui, "Aliases", tag_db.aliases_of(tag.id).map(RichText::new).collect());
+}
+
 pub struct Autocompleter{
     tag_db: Arc<TagAndImplicationDatabase>,
     last_result: Option<AutocompleteResult>,
+    /// index into `last_result.matches` of the suggestion highlighted with the arrow keys
+    selected: Option<usize>,
+    usage: TagUsage,
+    /// built the first time the fuzzy fallback is needed
+    fuzzy_index: OnceCell<HashMap<char, Vec<usize>>>,
+    /// whether to rank the user's own frequently searched tags first
+    pub personalize: bool,
+    /// how many suggestions to show from each tag category
+    pub per_category_limit: usize,
 }
 
 fn ptr_diff<T:?Sized>(p1: *const T, p2: *const T) -> usize {
     p2.addr() - p1.addr()
 }
 
+enum Suggestion {
+    /// A tag, by id in `AutocompleteResult::tag_db`, plus the alias that matched if it wasn't
+    /// the tag's own name.
+    Tag(u32, Option<String>),
+    /// Either a bare metatag key like `order:` or a complete one like `order:score`.
+    Metatag {
+        text: String,
+        description: &'static str,
+    },
+}
+
+/// Metatag keys starting with `prefix`, or if `prefix` already has a known key and a colon, the
+/// values for that key.  The second return value is true if `prefix` is definitely a metatag,
+/// meaning there's no point looking for regular tags.
+fn metatag_suggestions(prefix: &str) -> (Vec<Suggestion>, bool) {
+    if let Some((key, value_prefix)) = prefix.split_once(':') {
+        let Some(metatag) = metatags::get(key) else {
+            // plenty of real tags have colons in them
+            return (Vec::new(), false);
+        };
+        if metatag.values.is_empty() {
+            // numeric metatag -- nothing to suggest, but remind the user what it takes.
+            return (vec![Suggestion::Metatag { text: prefix.to_owned(), description: metatag.description }], true);
+        }
+        let values = metatag.values.iter()
+            .filter(|(value, _)| value.starts_with(value_prefix))
+            .map(|(value, description)| Suggestion::Metatag { text: format!("{}:{}", key, value), description })
+            .collect();
+        (values, true)
+    } else if prefix.is_empty() {
+        (Vec::new(), false)
+    } else {
+        let keys = metatags::METATAGS.iter()
+            .filter(|m| m.name.starts_with(prefix))
+            .map(|m| Suggestion::Metatag { text: format!("{}:", m.name), description: m.description })
+            .chain(CATEGORY_PREFIXES.iter()
+                .filter(|(name, _)| name.starts_with(prefix))
+                .map(|(name, _)| Suggestion::Metatag { text: format!("{}:", name), description: "only suggest tags in this category" }))
+            .collect();
+        (keys, false)
+    }
+}
+
+/// What to draw for one suggestion in the popup.
+struct Row<'a> {
+    left: String,
+    right: String,
+    color: Color32,
+    /// shown in a tooltip beside the row while it's hovered or highlighted
+    details: Option<&'a dyn Fn(&mut Ui)>,
+}
+
 struct AutocompleteResult {
-    // SAFETY: The "lifetime" of these pointers is the lifetime of the Arc.  It relies on the
-    // contents of the Arc not moving and the contents of all of the boxed slices inside that Arc
-    // not moving, which I think should be guaranteed by Arc immutability.
-    matches: Vec<(NonNull<Tag>, Option<NonNull<str>>)>,
+    /// The tag database the matches were looked up in.  Holding onto our own reference means the
+    /// app can swap in a freshly downloaded database while the popup is open without the
+    /// suggestions pointing at the wrong tags.
+    tag_db: Arc<TagAndImplicationDatabase>,
+    matches: Vec<Suggestion>,
+    /// everything in `matches` from here on came from the fuzzy fallback rather than an exact
+    /// prefix match
+    fuzzy_start: usize,
     token_range: Range<usize>,
 }
 
 impl Autocompleter {
     pub fn do_autocomplete(&mut self, search_query: &str, char_index: usize) -> bool {
-        let byte_index = search_query.char_indices().nth(char_index).map(|x| x.0).unwrap_or(search_query.len());
+        let byte_index = text_offsets::char_to_byte(search_query, char_index);
         if let Some((token, ancestors)) = parse_query_for_autocomplete(search_query, byte_index) {
             let token_start_byte_offset = (token.as_ptr() as usize - search_query.as_ptr() as usize);
             let cursor_idx_in_token = byte_index - token_start_byte_offset;
             let (prefix, suffix) = token.split_at(cursor_idx_in_token);
-            let ancestors = ancestors.into_iter().filter_map(|token| self.tag_db.get(token)).map(|x|x.id).collect::<Vec<_>>();
-            let matches = self.tag_db.autocomplete(prefix, MAX_AUTOCOMPLETION_COUNT, |tag, alias| {
-                !ancestors.contains(&tag.id) && alias.unwrap_or(tag.name.as_str()).ends_with(suffix)
-            });
-            let matches = matches.into_iter().map(|(a,b)| (a.into(), b.map(Into::into))).collect::<Vec<(NonNull<Tag>, Option<NonNull<str>>)>>();
+            let (mut matches, only_metatags) = metatag_suggestions(prefix);
+            let mut fuzzy_start = matches.len();
+            if !only_metatags {
+                let (category_filter, prefix) = match prefix.split_once(':') {
+                    Some((key, rest)) => match CATEGORY_PREFIXES.iter().find(|(name, _)| *name == key) {
+                        Some((_, category)) => (Some(*category), rest),
+                        None => (None, prefix),
+                    },
+                    None => (None, prefix),
+                };
+                let in_category = |tag: &Tag| category_filter.map_or(true, |c| tag.category == c);
+                let ancestors = ancestors.into_iter().filter_map(|token| self.tag_db.get(token)).map(|x|x.id).collect::<Vec<_>>();
+                let mut candidates = self.tag_db.autocomplete(prefix, CANDIDATE_POOL, |tag, alias| {
+                    in_category(tag) && !ancestors.contains(&tag.id) && alias.unwrap_or(tag.name.as_str()).ends_with(suffix)
+                });
+                if self.personalize {
+                    // stable sort, so tags the user has never searched for keep their order
+                    candidates.sort_by_key(|(tag, _)| std::cmp::Reverse(self.usage.get(tag.id)));
+                }
+                // group by category, so each one gets its own section in the popup
+                let mut tags = Vec::new();
+                for (category, _) in CATEGORY_ORDER {
+                    tags.extend(candidates.iter().filter(|(tag, _)| tag.category == category).take(self.per_category_limit).copied());
+                }
+                fuzzy_start += tags.len();
+                let remaining = MAX_AUTOCOMPLETION_COUNT.saturating_sub(matches.len() + tags.len());
+                if remaining > 0 && prefix.chars().count() >= MIN_FUZZY_QUERY_LEN {
+                    let exact = tags.iter().map(|(tag, _)| tag.id).collect::<Vec<_>>();
+                    let fuzzy = self.fuzzy_matches(prefix, remaining, |tag| {
+                        !in_category(tag) || exact.contains(&tag.id) || ancestors.contains(&tag.id)
+                    });
+                    tags.extend(fuzzy.into_iter().map(|tag| (tag, None)));
+                }
+                matches.extend(tags.into_iter().map(|(tag, alias)| Suggestion::Tag(tag.id, alias.map(str::to_owned))));
+            }
+            if matches.is_empty() {
+                self.last_result = None;
+                self.selected = None;
+                return false;
+            }
             let range_start = ptr_diff(search_query, token);
             let token_range = range_start..range_start+token.len();
-            self.last_result = Some(AutocompleteResult{matches, token_range});
+            self.last_result = Some(AutocompleteResult{tag_db: self.tag_db.clone(), matches, fuzzy_start, token_range});
+            self.selected = None;
             true
         } else {
             self.last_result = None;
+            self.selected = None;
             false
         }
     }
 
-    pub fn show_autocomplete_ui(&self, search_query: &mut String, ui: &mut Ui) -> Option<CCursorRange> {
-        let AutocompleteResult{token_range, matches} = self.last_result.as_ref().expect("do_autocomplete() should have been called first");
-
-        for (tag_ptr, alias_ptr) in matches {
-            let tag;
-            let alias;
-            unsafe {
-                // SAFETY: The "lifetime" of these pointers is the lifetime of self.tag_db.  These pointers being valid relies on the
-                // contents of the Arc not moving and the contents of all of the boxed slices inside that Arc not moving, which I think 
-                // should be guaranteed by Arc immutability.
-                //
-                // If at some point in the future I discover a way that those boxes might move, I'll panic, but for now I think I'm good.
-                tag = tag_ptr.as_ref();
-                alias = alias_ptr.map(|x|x.as_ref());
+    /// Finds up to `count` tags that are probably what the user meant to type, best first.  Ranks
+    /// by similarity to `prefix` weighted by how popular the tag is.  Only the `FUZZY_SCAN_LIMIT`
+    /// most popular plausible candidates are looked at, so the result doesn't depend on how fast
+    /// the machine is.
+    fn fuzzy_matches(&self, prefix: &str, count: usize, exclude: impl Fn(&Tag) -> bool) -> Vec<&Tag> {
+        let query = prefix.chars().collect::<Vec<_>>();
+        let tags = self.tag_db.tags();
+        let index = self.fuzzy_index.get_or_init(|| build_fuzzy_index(&self.tag_db));
+        // people rarely typo the very first letter, so only look at tags that have it somewhere in
+        // their first couple characters.
+        let Some(candidates) = index.get(&query[0]) else { return Vec::new() };
+        let mut scored: Vec<(f32, &Tag)> = Vec::new();
+        for tag in candidates.iter().take(FUZZY_SCAN_LIMIT).map(|i| &tags[*i]) {
+            if exclude(tag) {
+                continue;
+            }
+            if let Some(similarity) = fuzzy::score(&query, tag.name.as_str()) {
+                let popularity = (tag.post_count as f32 + 1.0).ln().max(1.0);
+                scored.push((similarity * popularity, tag));
             }
-            // egui does not natively support putting two text fields on the same row,
-            // so we have to manually implement a custom widget.
-            let color = match tag.category {
-                TagCategory::General => Color32::from_rgb(0xb4,0xc7,0xd9),
-                TagCategory::Artist => Color32::from_rgb(0xf2,0xac,0x08),
-                TagCategory::Copyright => Color32::from_rgb(0xdd,0x00,0xdd),
-                TagCategory::Character => Color32::from_rgb(0x00,0xaa,0x00),
-                TagCategory::Species => Color32::from_rgb(0xed,0x5d,0x1f),
-                TagCategory::Invalid => Color32::from_rgb(0xff,0x3d,0x3d),
-                TagCategory::Meta => Color32::from_rgb(0xff,0xff,0xff),
-                TagCategory::Lore => Color32::from_rgb(0x22,0x88,0x22),
+        }
+        scored.sort_unstable_by(|a, b| b.0.total_cmp(&a.0));
+        scored.truncate(count);
+        scored.into_iter().map(|(_, tag)| tag).collect()
+    }
+
+    /// Replaces the token being completed with the `idx`th suggestion and returns where the
+    /// cursor should go afterwards.
+    fn accept(&self, search_query: &mut String, idx: usize) -> Option<CCursorRange> {
+        let AutocompleteResult{tag_db, token_range, matches, ..} = self.last_result.as_ref()?;
+        let (text, add_space) = match matches.get(idx)? {
+            Suggestion::Tag(id, _) => (tag_db.get_by_id(*id)?.name.as_str(), true),
+            // leave the cursor right after the colon so the values pop up next
+            Suggestion::Metatag { text, .. } => (text.as_str(), !text.ends_with(':')),
+        };
+        // the query may have been edited since the suggestions were computed; don't replace
+        // something that no longer lines up with the text.
+        if token_range.end > search_query.len() || !search_query.is_char_boundary(token_range.start) || !search_query.is_char_boundary(token_range.end) {
+            return None;
+        }
+        search_query.replace_range(token_range.clone(), text);
+        let mut end_pos = token_range.start + text.len();
+        if add_space && end_pos == search_query.len() {
+            search_query.push(' ');
+            end_pos += 1;
+        }
+
+        let end = CCursor::new(text_offsets::byte_to_char(search_query, end_pos));
+
+        Some(CCursorRange::one(end))
+    }
+
+    /// Handles keyboard navigation while the popup is open.  This has to run *before* the search
+    /// box is drawn, so it can eat the keys before the text edit sees them.
+    ///
+    /// Up/Down move the highlight, Tab/Enter accept the highlighted suggestion (Tab takes the
+    /// first one if nothing is highlighted), and Escape closes the popup.
+    pub fn handle_keys(&mut self, ui: &mut Ui, search_query: &mut String) -> Option<CCursorRange> {
+        let count = self.last_result.as_ref().map_or(0, |r| r.matches.len());
+        if ui.input_mut(|i| i.consume_key(Modifiers::NONE, Key::Escape)) {
+            ui.memory_mut(|mem| mem.close_popup());
+            self.selected = None;
+            return None;
+        }
+        if count == 0 {
+            return None;
+        }
+        if ui.input_mut(|i| i.consume_key(Modifiers::NONE, Key::ArrowDown)) {
+            self.selected = Some(self.selected.map_or(0, |i| (i+1).min(count-1)));
+        }
+        if ui.input_mut(|i| i.consume_key(Modifiers::NONE, Key::ArrowUp)) {
+            // moving up off the first entry goes back to not having anything highlighted
+            self.selected = self.selected.and_then(|i| i.checked_sub(1));
+        }
+        let accept = if ui.input_mut(|i| i.consume_key(Modifiers::NONE, Key::Tab)) {
+            Some(self.selected.unwrap_or(0))
+        } else if self.selected.is_some() && ui.input_mut(|i| i.consume_key(Modifiers::NONE, Key::Enter)) {
+            self.selected
+        } else {
+            None
+        };
+        accept.and_then(|idx| self.accept(search_query, idx))
+    }
+
+    pub fn show_autocomplete_ui(&self, search_query: &mut String, ui: &mut Ui) -> Option<CCursorRange> {
+        // with a section per category there can be far more rows than fit on screen
+        ScrollArea::vertical().max_height(POPUP_MAX_HEIGHT).show(ui, |ui| self.show_rows(search_query, ui)).inner
+    }
+
+    fn show_rows(&self, search_query: &mut String, ui: &mut Ui) -> Option<CCursorRange> {
+        let AutocompleteResult{tag_db, matches, fuzzy_start, ..} = self.last_result.as_ref().expect("do_autocomplete() should have been called first");
+        let mut last_category = None;
+
+        for (i, suggestion) in matches.iter().enumerate() {
+            let (tag, alias) = match suggestion {
+                Suggestion::Tag(id, alias) => match tag_db.get_by_id(*id) {
+                    Some(tag) => (tag, alias.as_deref()),
+                    None => continue,
+                },
+                Suggestion::Metatag { text, description } => {
+                    if let Some(range) = self.show_row(ui, i, search_query, Row { left: text.to_string(), right: description.to_string(), color: METATAG_COLOR, details: None }) {
+                        return Some(range);
+                    }
+                    continue;
+                },
             };
+            if i < *fuzzy_start && last_category != Some(tag.category) {
+                last_category = Some(tag.category);
+                let heading = CATEGORY_ORDER.iter().find(|(c, _)| *c == tag.category).map_or("", |(_, heading)| heading);
+                ui.label(RichText::new(heading).small().weak());
+            }
+            let mut color = tag_category_color(tag.category);
+            if i >= *fuzzy_start {
+                // set the guesses apart from the real prefix matches
+                if i == *fuzzy_start {
+                    ui.separator();
+                }
+                color = color.gamma_multiply(0.6);
+            }
 
             let tag_name = match alias {
                 Some(alias) => {
@@ -79,46 +383,236 @@ impl Autocompleter {
                 },
                 None => tag.name.aliased(),
             };
-            let (response, painter) = ui.allocate_painter(Vec2::new(ui.available_width(), 20.0), Sense::click());
-
-            if response.hovered() || response.has_focus() {
-                painter.rect_filled(response.rect, ui.style().visuals.menu_rounding, ui.style().visuals.extreme_bg_color);
+            let details = |ui: &mut Ui| show_tag_details(ui, tag_db, tag);
+            if let Some(range) = self.show_row(ui, i, search_query, Row { left: tag_name.to_string(), right: tag.post_count.to_string(), color, details: Some(&details) }) {
+                return Some(range);
             }
+        }
 
-            if response.clicked() {
-                search_query.replace_range(token_range.clone(), tag.name.as_str());
-                let mut end_pos = token_range.start + tag.name.len();
-                if end_pos == search_query.len() {
-                    search_query.push(' ');
-                    end_pos += 1;
-                }
+        None
+    }
 
-                let end = CCursor::new(search_query[..end_pos].chars().count());
+    /// Draws one row of the popup, with `left` and `right` text on opposite sides.  Returns the new
+    /// cursor position if the row was clicked.
+    fn show_row(&self, ui: &mut Ui, i: usize, search_query: &mut String, row: Row<'_>) -> Option<CCursorRange> {
+        let Row { left, right, color, details } = row;
+        // egui does not natively support putting two text fields on the same row,
+        // so we have to manually implement a custom widget.
+        let (response, painter) = ui.allocate_painter(Vec2::new(ui.available_width(), 20.0), Sense::click());
 
-                return Some(CCursorRange::one(end));
+        let is_selected = self.selected == Some(i);
+        if response.hovered() || response.has_focus() || is_selected {
+            painter.rect_filled(response.rect, ui.style().visuals.menu_rounding, ui.style().visuals.extreme_bg_color);
+        }
+        if is_selected {
+            response.scroll_to_me(None);
+        }
+        if let Some(details) = details {
+            if response.hovered() || is_selected {
+                egui::show_tooltip_at(ui.ctx(), response.id.with("details"), Some(response.rect.right_top()), details);
             }
+        }
 
-            let font = FontSelection::Default.resolve(ui.style());
-            let name_galley = ui.fonts(|fonts| fonts.layout_no_wrap(tag_name.to_string(), font.clone(), color));
+        if response.clicked() {
+            return self.accept(search_query, i);
+        }
 
-            let mut post_count_job = LayoutJob::simple_singleline(tag.post_count.to_string(), font, color);
-            post_count_job.halign=Align::RIGHT;
-            let post_count_galley = ui.fonts(|fonts| fonts.layout_job(post_count_job));
+        let font = FontSelection::Default.resolve(ui.style());
+        let left_galley = ui.fonts(|fonts| fonts.layout_no_wrap(left, font.clone(), color));
 
-            let widget_rect = response.rect.shrink2(ui.style().spacing.button_padding);
+        let mut right_job = LayoutJob::simple_singleline(right, font, color);
+        right_job.halign=Align::RIGHT;
+        let right_galley = ui.fonts(|fonts| fonts.layout_job(right_job));
 
-            let name_top_offset = (widget_rect.height() - name_galley.rect.height()) / 2.0;
-            let post_count_top_offset = (widget_rect.height() - post_count_galley.rect.height()) / 2.0;
+        let widget_rect = response.rect.shrink2(ui.style().spacing.button_padding);
 
-            painter.galley(widget_rect.left_top() + Vec2::new(0.0, name_top_offset), name_galley, Color32::WHITE);
-            painter.galley(widget_rect.right_top() + Vec2::new(0.0, post_count_top_offset), post_count_galley, Color32::WHITE);
-        }
+        let left_top_offset = (widget_rect.height() - left_galley.rect.height()) / 2.0;
+        let right_top_offset = (widget_rect.height() - right_galley.rect.height()) / 2.0;
+
+        painter.galley(widget_rect.left_top() + Vec2::new(0.0, left_top_offset), left_galley, Color32::WHITE);
+        painter.galley(widget_rect.right_top() + Vec2::new(0.0, right_top_offset), right_galley, Color32::WHITE);
 
         None
+    }
+
+    /// Counts the real tags in a query that was just searched for, for personalized ranking.
+    /// Excluded (`-tag`) terms don't count, and nothing is recorded while personalization is off.
+    pub fn record_usage(&mut self, search_query: &str) {
+        if !self.personalize {
+            return;
+        }
+        let ids = search_query.split_whitespace()
+            .filter(|word| !word.starts_with('-'))
+            .map(|word| word.trim_start_matches('~'))
+            .filter_map(|word| self.tag_db.get(word))
+            .map(|tag| tag.id)
+            .collect::<Vec<_>>();
+        if !ids.is_empty() {
+            self.usage.record(ids);
+        }
+    }
+
+    /// Switches to a different tag database.  Any suggestions already on screen keep pointing at
+    /// the old one until the next call to `do_autocomplete`.
+    pub fn set_tag_db(&mut self, tag_db: Arc<TagAndImplicationDatabase>) {
+        self.tag_db = tag_db;
+        self.fuzzy_index = OnceCell::new();
+    }
+
+    pub(crate) fn new(tag_db: Arc<TagAndImplicationDatabase>, usage: TagUsage) -> Autocompleter {
+        Self {tag_db, last_result: None, selected: None, usage, fuzzy_index: OnceCell::new(), personalize: true, per_category_limit: 8}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures;
+
+    /// Names of the current suggestions, looked up the same way the popup does.
+    fn suggestion_names(ac: &Autocompleter) -> Vec<String> {
+        let Some(result) = ac.last_result.as_ref() else { return Vec::new() };
+        result.matches.iter().map(|s| match s {
+            Suggestion::Tag(id, _) => result.tag_db.get_by_id(*id).map_or_else(String::new, |t| t.name.as_str().to_owned()),
+            Suggestion::Metatag { text, .. } => text.clone(),
+        }).collect()
+    }
+
+    /// Autocompletes at character `cursor` of `query` and accepts the suggestion called `name`.
+    /// Returns the new query and cursor position.
+    fn complete_at(ac: &mut Autocompleter, query: &str, cursor: usize, name: &str) -> (String, usize) {
+        let mut query = query.to_owned();
+        assert!(ac.do_autocomplete(&query, cursor), "no suggestions for {:?}", query);
+        let names = suggestion_names(ac);
+        let idx = names.iter().position(|n| n == name).unwrap_or_else(|| panic!("{} not in {:?}", name, names));
+        let range = ac.accept(&mut query, idx).expect("accept should succeed");
+        (query, range.primary.index)
+    }
+
+    fn complete(ac: &mut Autocompleter, query: &str, name: &str) -> (String, usize) {
+        complete_at(ac, query, query.chars().count(), name)
+    }
+
+    /// Draws the popup in a headless egui context and returns whatever it returned.
+    fn render_popup(ac: &Autocompleter, query: &mut String) -> Option<CCursorRange> {
+        let ctx = egui::Context::default();
+        let mut result = None;
+        let _ = ctx.run(egui::RawInput::default(), |ctx| {
+            egui::CentralPanel::default().show(ctx, |ui| {
+                result = ac.show_autocomplete_ui(query, ui);
+            });
+        });
+        result
+    }
+
+    #[test]
+    fn popup_survives_database_reload() {
+        let mut ac = test_fixtures::autocompleter(test_fixtures::tag_db());
+        let mut query = "wol".to_owned();
+        assert!(ac.do_autocomplete(&query, 3));
+
+        // "wolf" doesn't exist in the new database and "fox" has a different id, so anything
+        // still looking at the current database would show or insert the wrong thing.
+        ac.set_tag_db(test_fixtures::reloaded_tag_db());
+        assert!(suggestion_names(&ac).contains(&"wolf".to_owned()));
+        assert_eq!(render_popup(&ac, &mut query), None);
+        assert_eq!(query, "wol");
+
+        let idx = suggestion_names(&ac).iter().position(|n| n == "wolf").unwrap();
+        let range = ac.accept(&mut query, idx).unwrap();
+        assert_eq!(query, "wolf ");
+        assert_eq!(range.primary.index, 5);
 
+        // the next keystroke picks up the new database
+        assert!(ac.do_autocomplete("wol", 3));
+        assert_eq!(suggestion_names(&ac), ["wolf_(character)"]);
     }
 
-    pub(crate) fn new(tag_db: Arc<TagAndImplicationDatabase>) -> Autocompleter {
-        Self {tag_db, last_result: None}
+    #[test]
+    fn reload_resets_fuzzy_index() {
+        let mut ac = test_fixtures::autocompleter(test_fixtures::tag_db());
+        // a typo, so the fuzzy fallback (and its index) gets used
+        assert!(ac.do_autocomplete("fxo", 3));
+        assert!(suggestion_names(&ac).contains(&"fox".to_owned()));
+        ac.set_tag_db(test_fixtures::reloaded_tag_db());
+        assert!(ac.do_autocomplete("fxo", 3));
+        let result = ac.last_result.as_ref().unwrap();
+        assert!(result.matches.iter().all(|s| matches!(s, Suggestion::Tag(id, _) if result.tag_db.get_by_id(*id).is_some())));
+        assert!(suggestion_names(&ac).contains(&"fox".to_owned()));
+    }
+
+    #[test]
+    fn non_ascii_queries() {
+        let mut ac = test_fixtures::autocompleter(test_fixtures::tag_db());
+        assert_eq!(complete(&mut ac, "caf", "café"), ("café ".to_owned(), 5));
+        assert_eq!(complete(&mut ac, "ポケ", "ポケモン"), ("ポケモン ".to_owned(), 5));
+        assert_eq!(complete(&mut ac, "ü", "über"), ("über ".to_owned(), 5));
+        // multi-byte text before the token being completed
+        assert_eq!(complete(&mut ac, "ポケモン caf", "café"), ("ポケモン café ".to_owned(), 10));
+        let mut query = "ポケ".to_owned();
+        assert!(ac.do_autocomplete(&query, 2));
+        assert_eq!(render_popup(&ac, &mut query), None);
+    }
+
+    #[test]
+    fn cursor_at_token_boundaries() {
+        let mut ac = test_fixtures::autocompleter(test_fixtures::tag_db());
+        // end of a token that isn't the last one: no extra space
+        assert_eq!(complete_at(&mut ac, "wol fox", 3, "wolf"), ("wolf fox".to_owned(), 4));
+        // middle of a token: the whole token gets replaced
+        assert_eq!(complete_at(&mut ac, "wol_girl", 3, "wolf_girl"), ("wolf_girl ".to_owned(), 10));
+        // past the end clamps to the end
+        assert_eq!(complete_at(&mut ac, "wol", 99, "wolf"), ("wolf ".to_owned(), 5));
+        // right after a multi-byte character
+        assert_eq!(complete_at(&mut ac, "café ポケ", 7, "ポケモン"), ("café ポケモン ".to_owned(), 10));
+    }
+
+    #[test]
+    fn cursor_inside_or_groups() {
+        let mut ac = test_fixtures::autocompleter(test_fixtures::tag_db());
+        assert_eq!(complete(&mut ac, "~fox ~wol", "wolf"), ("~fox ~wolf ".to_owned(), 11));
+        assert_eq!(complete_at(&mut ac, "~wol ~fox", 4, "wolf"), ("~wolf ~fox".to_owned(), 5));
+        assert_eq!(complete(&mut ac, "~ポケモン ~caf", "café"), ("~ポケモン ~café ".to_owned(), 12));
+        // inside a parenthesized group, not the last term
+        assert_eq!(complete_at(&mut ac, "( ~ポケ ~fox )", 5, "ポケモン"), ("( ~ポケモン ~fox )".to_owned(), 7));
+    }
+
+    #[test]
+    fn accept_after_query_shrank() {
+        let mut ac = test_fixtures::autocompleter(test_fixtures::tag_db());
+        assert!(ac.do_autocomplete("ポケモン caf", 8));
+        // the token range now points past the end, and then into the middle of a character
+        let mut query = "ポ".to_owned();
+        assert_eq!(ac.accept(&mut query, 0), None);
+        assert_eq!(query, "ポ");
+        let mut query = "ポケモンポケモン".to_owned();
+        assert_eq!(ac.accept(&mut query, 0), None);
+        assert_eq!(query, "ポケモンポケモン");
+    }
+
+    proptest::proptest! {
+        /// Whatever the query and cursor, accepting any suggestion mustn't panic, split a
+        /// character, touch text before the token, or put the cursor past the end.
+        #[test]
+        fn replacement_never_splits_characters(query in "[a-zéüポケモン漢~_() -]{0,12}", cursor in 0usize..16, edit in proptest::option::of(0usize..16)) {
+            let mut ac = test_fixtures::autocompleter(test_fixtures::tag_db());
+            ac.do_autocomplete(&query, cursor);
+            let count = ac.last_result.as_ref().map_or(0, |r| r.matches.len());
+            for idx in 0..count+1 {
+                let mut replaced = query.clone();
+                // sometimes the query has been edited since the suggestions were computed
+                if let Some(keep) = edit {
+                    replaced.truncate(text_offsets::char_to_byte(&replaced, keep));
+                }
+                let before = replaced.clone();
+                if let Some(range) = ac.accept(&mut replaced, idx) {
+                    let token_start = ac.last_result.as_ref().unwrap().token_range.start;
+                    proptest::prop_assert_eq!(&replaced[..token_start], &before[..token_start]);
+                    proptest::prop_assert!(range.primary.index <= replaced.chars().count());
+                } else {
+                    proptest::prop_assert_eq!(&replaced, &before);
+                }
+            }
+        }
     }
 }
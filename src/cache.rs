@@ -0,0 +1,86 @@
+use std::{io, path::{Path, PathBuf}, time::{Duration, SystemTime}};
+
+#[derive(Default, Debug)]
+pub struct CacheStats {
+    pub files: usize,
+    pub bytes: u64,
+    pub oldest: Option<SystemTime>,
+}
+
+struct CacheEntry {
+    path: PathBuf,
+    size: u64,
+    modified: SystemTime,
+}
+
+fn list_entries(dir: &Path) -> io::Result<Vec<CacheEntry>> {
+    let mut entries = Vec::new();
+    let read_dir = match std::fs::read_dir(dir) {
+        Ok(x) => x,
+        // no cache dir just means nothing's been cached yet
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(entries),
+        Err(e) => return Err(e),
+    };
+    for entry in read_dir {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            entries.extend(list_entries(&entry.path())?);
+        } else {
+            entries.push(CacheEntry {
+                path: entry.path(),
+                size: metadata.len(),
+                modified: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+            });
+        }
+    }
+    Ok(entries)
+}
+
+/// Everything under all of `dirs`.
+fn list_all_entries(dirs: &[PathBuf]) -> io::Result<Vec<CacheEntry>> {
+    let mut entries = Vec::new();
+    for dir in dirs {
+        entries.extend(list_entries(dir)?);
+    }
+    Ok(entries)
+}
+
+/// Counts the files under `dirs` and how much space they take up.
+pub fn stats(dirs: &[PathBuf]) -> io::Result<CacheStats> {
+    let entries = list_all_entries(dirs)?;
+    Ok(CacheStats {
+        files: entries.len(),
+        bytes: entries.iter().map(|e| e.size).sum(),
+        oldest: entries.iter().map(|e| e.modified).min(),
+    })
+}
+
+/// Deletes cached files older than `max_age`, then the least recently modified files until the
+/// files under all of `dirs` fit in `max_bytes` together.  Returns how many files were deleted and
+/// how many bytes that freed.
+pub fn prune(dirs: &[PathBuf], max_age: Option<Duration>, max_bytes: Option<u64>) -> io::Result<(usize, u64)> {
+    let mut entries = list_all_entries(dirs)?;
+    // newest first, so we can pop the oldest off the end
+    entries.sort_unstable_by(|a, b| b.modified.cmp(&a.modified));
+    let mut total = entries.iter().map(|e| e.size).sum::<u64>();
+    let now = SystemTime::now();
+    let (mut removed, mut freed) = (0, 0);
+    while let Some(oldest) = entries.last() {
+        let too_old = max_age.is_some_and(|max_age| now.duration_since(oldest.modified).unwrap_or_default() > max_age);
+        let too_big = max_bytes.is_some_and(|max_bytes| total > max_bytes);
+        if !too_old && !too_big {
+            break;
+        }
+        let oldest = entries.pop().unwrap();
+        match std::fs::remove_file(&oldest.path) {
+            Ok(()) => {
+                removed += 1;
+                freed += oldest.size;
+            },
+            Err(e) => tracing::warn!("Unable to remove {}: {}", oldest.path.display(), e),
+        }
+        total -= oldest.size;
+    }
+    Ok((removed, freed))
+}
@@ -0,0 +1,153 @@
+use std::{path::Path, time::Duration};
+
+use directories::ProjectDirs;
+
+use crate::{cache, export::{self, ExportFormat}, post_info::format_size, ruffle_util::sqlite_storage, search};
+
+const USAGE: &str = "usage:
+    vince621                                  start the GUI
+    vince621 search <query> [--limit N] [--format ids|urls|csv|jsonl] [--output FILE] [--blacklist QUERY]...
+    vince621 import <dir>                     import tags.v621 and posts.v621 from <dir>
+    vince621 cache stats
+    vince621 cache prune [--max-age DAYS] [--max-size MB]
+    vince621 flash-saves migrate to-sqlite|to-disk";
+
+/// Pulls `--flag value` pairs out of `args`, leaving the positional arguments behind.
+fn take_flag(args: &mut Vec<String>, flag: &str) -> Result<Option<String>, String> {
+    let Some(pos) = args.iter().position(|a| a == flag) else { return Ok(None) };
+    if pos+1 >= args.len() {
+        return Err(format!("{} needs a value", flag));
+    }
+    let value = args.remove(pos+1);
+    args.remove(pos);
+    Ok(Some(value))
+}
+
+fn parse_flag<T: std::str::FromStr>(args: &mut Vec<String>, flag: &str) -> Result<Option<T>, String> {
+    take_flag(args, flag)?.map(|v| v.parse().map_err(|_| format!("invalid value for {}: {}", flag, v))).transpose()
+}
+
+/// Runs a command-line subcommand, if there is one.  Returns `None` if the GUI should be started
+/// instead, otherwise the process exit code.
+pub fn run(args: &[String], proj_dirs: &ProjectDirs) -> Option<i32> {
+    let mut args = args.get(1..).unwrap_or_default().to_vec();
+    let command = match args.first() {
+        None => return None,
+        Some(x) => x.clone(),
+    };
+    args.remove(0);
+    let result = match command.as_str() {
+        "search" => search_command(args, proj_dirs),
+        "import" => match args.first() {
+            Some(dir) => import_command(Path::new(dir), proj_dirs.cache_dir()),
+            None => Err("import needs a directory".into()),
+        },
+        "cache" => cache_command(args, proj_dirs),
+        "flash-saves" => flash_saves_command(args, proj_dirs),
+        "help" | "--help" | "-h" => {
+            println!("{}", USAGE);
+            Ok(())
+        },
+        other => Err(format!("unknown command {}\n{}", other, USAGE)),
+    };
+    match result {
+        Ok(()) => Some(0),
+        Err(e) => {
+            eprintln!("{}", e);
+            Some(1)
+        }
+    }
+}
+
+fn search_command(mut args: Vec<String>, proj_dirs: &ProjectDirs) -> Result<(), String> {
+    let limit = parse_flag::<usize>(&mut args, "--limit")?;
+    let format = match take_flag(&mut args, "--format")? {
+        Some(f) => ExportFormat::from_name(&f).ok_or_else(|| format!("unknown format {}", f))?,
+        None => ExportFormat::Ids,
+    };
+    let output = take_flag(&mut args, "--output")?;
+    let mut blacklist_queries = Vec::new();
+    while let Some(q) = take_flag(&mut args, "--blacklist")? {
+        blacklist_queries.push(q);
+    }
+    let [query] = args.as_slice() else {
+        return Err(USAGE.into());
+    };
+
+    let (tag_db, post_db) = crate::load_databases(proj_dirs.cache_dir())?;
+    let (query, sort_order) = search::parse_query(&tag_db, query).map_err(|(_, reason)| format!("Error parsing query: {}", reason))?;
+    let blacklist = blacklist_queries.iter()
+        .map(|q| search::parse_query(&tag_db, q).map(|(q, _)| q).map_err(|(_, reason)| format!("Error parsing blacklist query {}: {}", q, reason)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let posts = post_db.get_all();
+    let mut results = search::search_all(posts, &query, sort_order);
+    // filter before applying the limit, so --limit N still gets N posts
+    results.retain(|idx| !search::is_blacklisted(&blacklist, &posts[*idx]));
+    if let Some(limit) = limit {
+        results.truncate(limit);
+    }
+    let written = match output {
+        Some(path) => std::fs::File::create(path).and_then(|f| export::export_results(f, format, &tag_db, posts, &results, &blacklist)),
+        None => export::export_results(std::io::stdout().lock(), format, &tag_db, posts, &results, &blacklist),
+    };
+    let count = written.map_err(|e| format!("Error exporting results: {}", e))?;
+    eprintln!("exported {} posts", count);
+    Ok(())
+}
+
+/// Copies a pair of database files into the cache, after making sure they actually load.
+fn import_command(dir: &Path, cache_dir: &Path) -> Result<(), String> {
+    crate::load_databases(dir)?;
+    std::fs::create_dir_all(cache_dir).map_err(|e| format!("Error creating {}: {}", cache_dir.display(), e))?;
+    for name in ["tags.v621", "posts.v621"] {
+        // copy next to the destination first so a failed copy doesn't leave a half-written database
+        let tmp = cache_dir.join(format!("{}.tmp", name));
+        std::fs::copy(dir.join(name), &tmp)
+            .and_then(|_| std::fs::rename(&tmp, cache_dir.join(name)))
+            .map_err(|e| format!("Error importing {}: {}", name, e))?;
+    }
+    println!("imported databases from {}", dir.display());
+    Ok(())
+}
+
+fn cache_command(mut args: Vec<String>, proj_dirs: &ProjectDirs) -> Result<(), String> {
+    // downloaded images, and assets Flash movies loaded
+    let dirs = [proj_dirs.cache_dir().join("images"), proj_dirs.cache_dir().join("flash_assets")];
+    let max_age = parse_flag::<u64>(&mut args, "--max-age")?.map(|days| Duration::from_secs(days * 24 * 60 * 60));
+    let max_size = parse_flag::<u64>(&mut args, "--max-size")?.map(|mb| mb * 1024 * 1024);
+    match args.first().map(String::as_str) {
+        Some("stats") => {
+            for dir in dirs.iter() {
+                let stats = cache::stats(std::slice::from_ref(dir)).map_err(|e| format!("Error reading cache: {}", e))?;
+                println!("{}: {} files, {}", dir.display(), stats.files, format_size(stats.bytes));
+                if let Some(age) = stats.oldest.and_then(|t| t.elapsed().ok()) {
+                    println!("oldest file is {} days old", age.as_secs() / (24 * 60 * 60));
+                }
+            }
+            Ok(())
+        },
+        Some("prune") => {
+            if max_age.is_none() && max_size.is_none() {
+                return Err("cache prune needs --max-age and/or --max-size".into());
+            }
+            let (removed, freed) = cache::prune(&dirs, max_age, max_size).map_err(|e| format!("Error pruning cache: {}", e))?;
+            println!("removed {} files, freed {}", removed, format_size(freed));
+            Ok(())
+        },
+        _ => Err(USAGE.into()),
+    }
+}
+
+/// Copies Flash saves between the one-file-per-object layout and the SQLite database.  The source
+/// is left as it was, so it can be deleted by hand once the copy looks right.
+fn flash_saves_command(args: Vec<String>, proj_dirs: &ProjectDirs) -> Result<(), String> {
+    let saves_dir = proj_dirs.data_dir().join("flash_saves");
+    let db_path = proj_dirs.data_dir().join("flash_saves.sqlite");
+    let count = match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        ["migrate", "to-sqlite"] => sqlite_storage::migrate_from_disk(&saves_dir, &db_path)?,
+        ["migrate", "to-disk"] => sqlite_storage::migrate_to_disk(&db_path, &saves_dir)?,
+        _ => return Err(USAGE.into()),
+    };
+    println!("copied {} shared objects", count);
+    Ok(())
+}
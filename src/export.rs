@@ -0,0 +1,202 @@
+use std::{fmt::Write as _, io::{self, Write}, path::Path, sync::{Arc, Mutex}};
+
+use vince621_core::{db::{posts::{ImageResolution, Post, PostDatabase}, tags::TagAndImplicationDatabase}, search::{e6_posts::PostKernel, NestedQuery}};
+
+use crate::search::is_blacklisted;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ExportFormat {
+    /// one post id per line
+    Ids,
+    /// one full-size file URL per line
+    Urls,
+    /// one metadata row per post, with a header
+    Csv,
+    /// one JSON object of metadata per line
+    JsonLines,
+}
+
+impl ExportFormat {
+    pub const ALL: [ExportFormat; 4] = [ExportFormat::Ids, ExportFormat::Urls, ExportFormat::Csv, ExportFormat::JsonLines];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ExportFormat::Ids => "ids",
+            ExportFormat::Urls => "urls",
+            ExportFormat::Csv => "csv",
+            ExportFormat::JsonLines => "jsonl",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ExportFormat> {
+        Self::ALL.into_iter().find(|f| f.name() == name)
+    }
+}
+
+const CSV_HEADER: [&str; 11] = ["id", "url", "file_ext", "rating", "score", "fav_count", "width", "height", "file_size", "created_at", "tags"];
+
+/// The metadata columns for a post, in the same order as `CSV_HEADER`.
+fn metadata_row(tag_db: &TagAndImplicationDatabase, post: &Post) -> [String; 11] {
+    let tags = post.tags.iter().filter_map(|id| tag_db.get_by_id(*id)).map(|tag| tag.name.as_str()).collect::<Vec<_>>().join(" ");
+    [
+        post.id.get().to_string(),
+        post.url(ImageResolution::Full).to_string(),
+        format!("{:?}", post.file_ext).to_lowercase(),
+        format!("{:?}", post.rating).to_lowercase(),
+        post.score.to_string(),
+        post.fav_count.to_string(),
+        post.width.to_string(),
+        post.height.to_string(),
+        post.file_size.to_string(),
+        post.created_at.to_string(),
+        tags,
+    ]
+}
+
+fn json_escape(s: &str, out: &mut String) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => { let _ = write!(out, "\\u{:04x}", c as u32); },
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// Writes `results` (indices into `posts`, already in the order the user sees them) to `out`,
+/// skipping anything matched by `blacklist`.  Returns how many posts were written.
+pub fn export_results<W: Write>(out: W, format: ExportFormat, tag_db: &TagAndImplicationDatabase, posts: &[Post], results: &[usize], blacklist: &[NestedQuery<PostKernel>]) -> io::Result<usize> {
+    let visible = results.iter().map(|idx| &posts[*idx]).filter(|post| !is_blacklisted(blacklist, post));
+    let mut count = 0;
+    match format {
+        ExportFormat::Ids | ExportFormat::Urls => {
+            let mut out = io::BufWriter::new(out);
+            for post in visible {
+                if format == ExportFormat::Ids {
+                    writeln!(out, "{}", post.id)?;
+                } else {
+                    writeln!(out, "{}", post.url(ImageResolution::Full))?;
+                }
+                count += 1;
+            }
+            out.flush()?;
+        },
+        ExportFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(out);
+            writer.write_record(CSV_HEADER)?;
+            for post in visible {
+                writer.write_record(metadata_row(tag_db, post))?;
+                count += 1;
+            }
+            writer.flush()?;
+        },
+        ExportFormat::JsonLines => {
+            let mut out = io::BufWriter::new(out);
+            let mut line = String::new();
+            for post in visible {
+                line.clear();
+                line.push('{');
+                for (i, (key, value)) in CSV_HEADER.iter().zip(metadata_row(tag_db, post)).enumerate() {
+                    if i > 0 {
+                        line.push(',');
+                    }
+                    json_escape(key, &mut line);
+                    line.push(':');
+                    // numeric columns go out as numbers, everything else as strings
+                    if matches!(*key, "id" | "score" | "fav_count" | "width" | "height" | "file_size") {
+                        line.push_str(&value);
+                    } else {
+                        json_escape(&value, &mut line);
+                    }
+                }
+                line.push('}');
+                writeln!(out, "{}", line)?;
+                count += 1;
+            }
+            out.flush()?;
+        },
+    }
+    Ok(count)
+}
+
+/// Where the export started from the window has got to.
+enum ExportState {
+    Idle,
+    Running,
+    Done(String),
+}
+
+/// State for the "Export results" window.
+pub struct ExportDialog {
+    path: String,
+    format: ExportFormat,
+    tag_db: Arc<TagAndImplicationDatabase>,
+    post_db: Arc<PostDatabase>,
+    /// the results on screen when the window was opened, with blacklisted posts already taken out
+    results: Arc<Vec<usize>>,
+    /// shared with the export thread
+    state: Arc<Mutex<ExportState>>,
+}
+
+impl ExportDialog {
+    pub fn new(default_dir: &Path, tag_db: Arc<TagAndImplicationDatabase>, post_db: Arc<PostDatabase>, results: &[usize], blacklist: &[NestedQuery<PostKernel>]) -> Self {
+        let posts = post_db.get_all();
+        let results = results.iter().copied().filter(|idx| !is_blacklisted(blacklist, &posts[*idx])).collect();
+        Self {
+            path: default_dir.join("results.csv").to_string_lossy().into_owned(),
+            format: ExportFormat::Csv,
+            tag_db,
+            post_db,
+            results: Arc::new(results),
+            state: Arc::new(Mutex::new(ExportState::Idle)),
+        }
+    }
+
+    /// Writes the results out on the rayon pool, so a big export doesn't freeze the window.
+    fn start_export(&self, ctx: &egui::Context) {
+        *self.state.lock().unwrap() = ExportState::Running;
+        let (path, format) = (self.path.clone(), self.format);
+        let (tag_db, post_db, results, state) = (self.tag_db.clone(), self.post_db.clone(), self.results.clone(), self.state.clone());
+        let ctx = ctx.clone();
+        rayon::spawn(move || {
+            let result = std::fs::File::create(&path).and_then(|f| export_results(f, format, &tag_db, post_db.get_all(), &results, &[]));
+            *state.lock().unwrap() = ExportState::Done(match result {
+                Ok(count) => format!("Exported {} posts", count),
+                Err(e) => format!("Export failed: {}", e),
+            });
+            ctx.request_repaint();
+        });
+    }
+
+    /// Draws the export window.  Returns false once the user closes it.
+    pub fn show(&mut self, ctx: &egui::Context) -> bool {
+        let mut open = true;
+        egui::Window::new("Export results").open(&mut open).collapsible(false).show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.label("File:");
+                ui.text_edit_singleline(&mut self.path);
+            });
+            ui.horizontal(|ui| {
+                for format in ExportFormat::ALL {
+                    ui.radio_value(&mut self.format, format, format.name());
+                }
+            });
+            let running = matches!(*self.state.lock().unwrap(), ExportState::Running);
+            if ui.add_enabled(!running, egui::Button::new(format!("Export {} results", self.results.len()))).clicked() {
+                self.start_export(ctx);
+            }
+            match *self.state.lock().unwrap() {
+                ExportState::Idle => {},
+                ExportState::Running => { ui.spinner(); },
+                ExportState::Done(ref status) => { ui.label(status); },
+            }
+        });
+        open
+    }
+}
@@ -0,0 +1,63 @@
+//! Typo-tolerant string matching for the tag autocompleter.
+
+/// Levenshtein distance between `a` and `b`, or `None` if it's more than `max`.  Gives up as soon
+/// as a whole row of the table is over the limit, so most non-matches are cheap.
+pub fn bounded_edit_distance(a: &[char], b: &[char], max: usize) -> Option<usize> {
+    if a.len().abs_diff(b.len()) > max {
+        return None;
+    }
+    let mut prev = (0..=b.len()).collect::<Vec<_>>();
+    let mut cur = vec![0; b.len()+1];
+    for (i, ca) in a.iter().enumerate() {
+        cur[0] = i+1;
+        let mut row_min = cur[0];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = prev[j] + (ca != cb) as usize;
+            cur[j+1] = substitution.min(prev[j+1]+1).min(cur[j]+1);
+            row_min = row_min.min(cur[j+1]);
+        }
+        if row_min > max {
+            return None;
+        }
+        std::mem::swap(&mut prev, &mut cur);
+    }
+    Some(prev[b.len()]).filter(|d| *d <= max)
+}
+
+/// True if every character of `needle` shows up in `haystack` in order.
+pub fn is_subsequence(needle: &[char], haystack: &str) -> bool {
+    let mut haystack = haystack.chars();
+    needle.iter().all(|c| haystack.any(|h| h == *c))
+}
+
+/// How many typos we'll forgive in a query of `len` characters.
+fn max_typos(len: usize) -> usize {
+    match len {
+        0..=4 => 1,
+        5..=8 => 2,
+        _ => 3,
+    }
+}
+
+/// Scores how well `candidate` matches what the user has typed so far, between 0 and 1, or
+/// `None` if it isn't a plausible match at all.
+///
+/// Since the user is usually partway through typing, `query` is compared against the start of
+/// the candidate (give or take a character) rather than the whole thing.  Candidates that merely
+/// contain the query as a subsequence get a lower score than ones within a few typos.
+pub fn score(query: &[char], candidate: &str) -> Option<f32> {
+    let candidate_chars = candidate.chars().collect::<Vec<_>>();
+    let max = max_typos(query.len());
+    let lo = query.len().saturating_sub(1);
+    let hi = (query.len()+1).min(candidate_chars.len());
+    let best = (lo..=hi)
+        .filter_map(|len| bounded_edit_distance(query, &candidate_chars[..len], max))
+        .min();
+    if let Some(distance) = best {
+        return Some(1.0 - distance as f32 / (query.len() as f32 + 1.0));
+    }
+    if is_subsequence(query, candidate) {
+        return Some(0.4 * query.len() as f32 / candidate_chars.len() as f32);
+    }
+    None
+}
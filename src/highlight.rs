@@ -0,0 +1,108 @@
+use std::ops::Range;
+
+use egui::{text::{LayoutJob, TextFormat}, Color32, FontSelection, Stroke, Ui};
+use vince621_core::db::tags::TagAndImplicationDatabase;
+
+use crate::{autocomplete::tag_category_color, metatags};
+
+const NEGATION_COLOR: Color32 = Color32::from_rgb(0xff,0x60,0x60);
+const OR_COLOR: Color32 = Color32::from_rgb(0x60,0xa0,0xff);
+const GROUP_COLOR: Color32 = Color32::from_rgb(0xc0,0xc0,0xc0);
+pub(crate) const METATAG_COLOR: Color32 = Color32::from_rgb(0xd0,0x90,0xff);
+const UNKNOWN_TAG_UNDERLINE: Color32 = Color32::from_rgb(0xff,0x3d,0x3d);
+const ERROR_BACKGROUND: Color32 = Color32::from_rgb(0x60,0x10,0x10);
+
+/// Splits a query into whitespace-separated words, returning the byte range of each one.
+fn words(query: &str) -> impl Iterator<Item=Range<usize>> + '_ {
+    query.char_indices()
+        .filter(|&(i, c)| !c.is_whitespace() && query[..i].chars().next_back().map_or(true, char::is_whitespace))
+        .map(|(start, _)| {
+            let len = query[start..].find(char::is_whitespace).unwrap_or(query.len()-start);
+            start..start+len
+        })
+}
+
+/// Builds a colored layout for the search box.
+///
+/// Tags are colored by category, negations, OR markers, groups and metatags get their own colors,
+/// tags that don't exist in the database are underlined, and `error_range` (in bytes) gets a red
+/// background.
+pub fn highlight_query(ui: &Ui, tag_db: &TagAndImplicationDatabase, query: &str, error_range: Option<Range<usize>>) -> LayoutJob {
+    let font_id = FontSelection::Default.resolve(ui.style());
+    let default_color = ui.visuals().text_color();
+    let plain = TextFormat::simple(font_id.clone(), default_color);
+    let colored = |color| TextFormat::simple(font_id.clone(), color);
+    // the error range is from the last search, so it might not line up with the text anymore.
+    let error_range = error_range.filter(|e| e.end <= query.len() && query.is_char_boundary(e.start) && query.is_char_boundary(e.end));
+
+    // (range, format) pairs covering the whole query, in order.
+    let mut spans: Vec<(Range<usize>, TextFormat)> = Vec::new();
+    let mut last_end = 0;
+    for word in words(query) {
+        if word.start > last_end {
+            spans.push((last_end..word.start, plain.clone()));
+        }
+        last_end = word.end;
+
+        let mut start = word.start;
+        let text = &query[word.clone()];
+        match text {
+            "{" | "}" | "(" | ")" => {
+                spans.push((word, colored(GROUP_COLOR)));
+                continue;
+            },
+            _ => {},
+        }
+        let prefix_color = match text.as_bytes()[0] {
+            b'-' => Some(NEGATION_COLOR),
+            b'~' => Some(OR_COLOR),
+            _ => None,
+        };
+        if let Some(color) = prefix_color {
+            spans.push((start..start+1, colored(color)));
+            start += 1;
+            if start == word.end {
+                continue;
+            }
+        }
+        let name = &query[start..word.end];
+        let format = if name.split_once(':').is_some_and(|(key, _)| metatags::get(key).is_some()) {
+            colored(METATAG_COLOR)
+        } else if name.contains('*') {
+            plain.clone()
+        } else if let Some(tag) = tag_db.get(name) {
+            colored(tag_category_color(tag.category))
+        } else {
+            TextFormat {
+                underline: Stroke::new(1.0, UNKNOWN_TAG_UNDERLINE),
+                ..plain.clone()
+            }
+        };
+        spans.push((start..word.end, format));
+    }
+    if last_end < query.len() {
+        spans.push((last_end..query.len(), plain.clone()));
+    }
+
+    let mut job = LayoutJob::default();
+    for (range, format) in spans {
+        // split the span wherever it crosses the edge of the error range.
+        let mut cuts = vec![range.start, range.end];
+        if let Some(ref error) = error_range {
+            cuts.extend([error.start, error.end].into_iter().filter(|x| range.start < *x && *x < range.end));
+        }
+        cuts.sort_unstable();
+        for piece in cuts.windows(2) {
+            let (a, b) = (piece[0], piece[1]);
+            if a == b {
+                continue;
+            }
+            let mut format = format.clone();
+            if error_range.as_ref().is_some_and(|e| e.start <= a && b <= e.end) {
+                format.background = ERROR_BACKGROUND;
+            }
+            job.append(&query[a..b], 0.0, format);
+        }
+    }
+    job
+}
@@ -1,23 +1,17 @@
 #![feature(strict_provenance)]
-use std::{sync::{Arc, Mutex, MutexGuard}, time::Instant};
+use std::{ops::Range, path::Path, sync::{atomic::{AtomicUsize, Ordering}, Arc, Mutex, MutexGuard}, time::Instant};
 
 use directories::ProjectDirs;
 use eframe::{egui_wgpu::WgpuConfiguration, wgpu::{self, PowerPreference}};
 use eframe::NativeOptions;
 use eframe::egui::{self, CentralPanel, Key, ProgressBar, TextEdit, TopBottomPanel};
-use egui::{load::BytesPoll, popup_below_widget, text::{CCursor, LayoutJob}, text_edit::TextEditState, text_selection::CCursorRange, Align, Color32, FontSelection, Id, Layout, Rect, RichText, Sense, Ui, Vec2, ViewportBuilder, WidgetText};
-use http_body_util::Empty;
-use hyper_tls::HttpsConnector;
-use hyper_util::client::legacy::connect::HttpConnector;
-use rand::seq::SliceRandom as _;
-use rayon::{iter::{IndexedParallelIterator as _, ParallelIterator as _}, slice::ParallelSliceMut as _};
-use ruffle_core::{tag_utils::SwfMovie, PlayerBuilder};
-use vince621_core::{db::{posts::{FileExtension, ImageResolution, PostDatabase}, tags::{TagAndImplicationDatabase, TagCategory}}, search::{e6_posts::{parse_query_for_autocomplete, PostKernel, SortOrder}, NestedQuery}};
+use egui::{load::BytesPoll, EventFilter, popup_below_widget, text::{CCursor, LayoutJob}, text_edit::TextEditState, text_selection::CCursorRange, Align, Color32, FontSelection, Id, Layout, Rect, RichText, Sense, Ui, Vec2, ViewportBuilder, WidgetText};
+use rayon::iter::{IndexedParallelIterator as _, ParallelIterator as _};
+use ruffle_core::{backend::storage::StorageBackend, tag_utils::SwfMovie, PlayerBuilder};
+use vince621_core::{db::{posts::{FileExtension, ImageResolution, PostDatabase}, tags::{TagAndImplicationDatabase, TagCategory}}, search::{e6_posts::{parse_query_for_autocomplete, PostKernel}, NestedQuery}};
 
 use byteyarn::yarn;
 
-use paste::paste;
-
 use egui_ruffle::{Descriptors, EguiRufflePlayer};
 
 /*
@@ -28,13 +22,47 @@ use image_loader::loader::ImageLoader;
 
 mod ruffle_util;
 use ruffle_util::storage::DiskStorageBackend;
+use ruffle_util::controls::{self, ControlAction, FlashPrefsStore};
+use ruffle_util::audio::GlobalVolume;
+use ruffle_util::navigator::{FlashExecutor, SandboxedNavigator};
+use ruffle_util::log_backend::{ConsoleLogBackend, FlashConsole};
+use ruffle_util::swf_info::SwfInfo;
+use ruffle_util::ui_backend::{EguiUiBackend, FlashUiState};
 
 mod autocomplete;
 use autocomplete::Autocompleter;
 
-mod db_download;
+mod fuzzy;
+
+mod text_offsets;
+
+mod usage;
+use usage::TagUsage;
+
+mod highlight;
+
+mod metatags;
+
+mod search;
+
+mod export;
+use export::ExportDialog;
+
+mod cache;
+
+mod cli;
 
-type Client = hyper_util::client::legacy::Client<HttpsConnector<HttpConnector>,Empty<&'static [u8]>>;
+mod related_tags;
+use related_tags::RelatedTags;
+
+mod post_info;
+use post_info::TagAction;
+
+#[cfg(test)]
+mod test_fixtures;
+
+mod save_manager;
+use save_manager::{FlashStorage, SaveManager};
 
 enum UiState {
     ShowText(String),
@@ -42,20 +70,85 @@ enum UiState {
     ShowPosts(Vec<usize>, usize),
 }
 
-#[derive(Default)]
+/// One level of the "search within results" chain.  Holds everything needed to pop back to it.
+struct Refinement {
+    query: String,
+    results: Vec<usize>,
+    idx: usize,
+}
+
+/// Reads a setting written by `save_setting`.  `None` if it was never saved or doesn't parse.
+fn load_setting<T: std::str::FromStr>(data_dir: &Path, name: &str) -> Option<T> {
+    std::fs::read_to_string(data_dir.join(format!("{}.txt", name))).ok()?.trim().parse().ok()
+}
+
+/// Saves one setting to its own file in the data dir, like `FlashStorage::save`.
+fn save_setting(data_dir: &Path, name: &str, value: impl std::fmt::Display) {
+    let path = data_dir.join(format!("{}.txt", name));
+    if let Err(e) = std::fs::create_dir_all(data_dir).and_then(|()| std::fs::write(&path, value.to_string())) {
+        println!("couldn't save setting to {}: {}", path.display(), e);
+    }
+}
+
 struct Settings {
     settings_dialog_is_open: bool,
     user_blacklist: Vec<NestedQuery<PostKernel>>,
+    /// saved to the data dir whenever it changes
+    personalized_autocomplete: bool,
+    autocomplete_per_category: usize,
+    /// saved to the data dir whenever it changes, unlike the rest
+    flash_storage: FlashStorage,
+    /// 0 to 1, for every Flash movie.  Saved to the data dir along with `flash_muted`.
+    flash_volume: f32,
+    flash_muted: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            settings_dialog_is_open: false,
+            user_blacklist: Vec::new(),
+            personalized_autocomplete: true,
+            autocomplete_per_category: 8,
+            flash_storage: FlashStorage::Files,
+            flash_volume: 1.0,
+            flash_muted: false,
+        }
+    }
 }
 
 struct App {
     search_query: String,
+    /// the query that produced the results currently being shown
+    active_query: String,
+    refinements: Vec<Refinement>,
+    /// byte range and reason of the last parse error, shown under the search box
+    search_error: Option<(Range<usize>, String)>,
     ui_state: Arc<Mutex<UiState>>,
+    related_tags: Arc<RelatedTags>,
+    show_post_info: bool,
+    export_dialog: Option<ExportDialog>,
+    save_manager: Option<SaveManager>,
     tag_db: Arc<TagAndImplicationDatabase>,
     post_db: Arc<PostDatabase>,
+    /// databases loaded in the background by File > Reload databases, waiting to be swapped in
+    reloaded_databases: Arc<Mutex<Option<Result<(TagAndImplicationDatabase, PostDatabase), String>>>>,
+    /// bumped whenever the databases are swapped, so searches still running against the old ones
+    /// know to throw their results away
+    db_generation: Arc<AtomicUsize>,
     settings: Arc<Mutex<Settings>>,
     ruffle_descriptors: Arc<Descriptors>,
     flashplayer: Option<EguiRufflePlayer>,
+    /// why the movie of the given post couldn't be played
+    flash_error: Option<(u32, String)>,
+    /// header of the movie of the given post
+    flash_info: Option<(u32, SwfInfo)>,
+    flash_ui: Arc<Mutex<FlashUiState>>,
+    flash_prefs: FlashPrefsStore,
+    flash_volume: GlobalVolume,
+    flash_executor: FlashExecutor,
+    flash_console: Arc<Mutex<FlashConsole>>,
+    show_flash_console: bool,
     project_dirs: ProjectDirs,
     autocompleter: Autocompleter,
 }
@@ -66,78 +159,221 @@ impl App {
         egui_extras::install_image_loaders(&ctx.egui_ctx);
         Self {
             search_query: String::new(),
-            autocompleter: Autocompleter::new(tag_db.clone()),
+            active_query: String::new(),
+            refinements: Vec::new(),
+            search_error: None,
+            autocompleter: Autocompleter::new(tag_db.clone(), TagUsage::load(project_dirs.data_dir().join("tag_usage.csv"))),
             ui_state: Arc::new(Mutex::new(UiState::ShowText("Enter a search query".into()))),
+            related_tags: Arc::new(RelatedTags::default()),
+            show_post_info: true,
+            export_dialog: None,
+            save_manager: None,
             tag_db,
             post_db: Arc::new(post_db),
-            settings: Arc::new(Mutex::new(Settings::default())),
+            reloaded_databases: Arc::new(Mutex::new(None)),
+            db_generation: Arc::new(AtomicUsize::new(0)),
+            settings: Arc::new(Mutex::new(Settings {
+                personalized_autocomplete: load_setting(project_dirs.data_dir(), "personalized_autocomplete").unwrap_or(true),
+                flash_storage: FlashStorage::load(project_dirs.data_dir()),
+                flash_volume: load_setting::<f32>(project_dirs.data_dir(), "flash_volume").unwrap_or(1.0).clamp(0.0, 1.0),
+                flash_muted: load_setting(project_dirs.data_dir(), "flash_muted").unwrap_or(false),
+                ..Settings::default()
+            })),
             flashplayer: None,
+            flash_error: None,
+            flash_info: None,
+            flash_ui: Arc::new(Mutex::new(FlashUiState::default())),
+            flash_prefs: FlashPrefsStore::load(project_dirs.data_dir().join("flash_prefs.csv")),
+            flash_volume: GlobalVolume::default(),
+            flash_executor: FlashExecutor::new(ctx.egui_ctx.clone()),
+            flash_console: Arc::new(Mutex::new(FlashConsole::default())),
+            show_flash_console: false,
             ruffle_descriptors: Arc::new(egui_ruffle::create_descriptors_from_render_state(ctx.wgpu_render_state.as_ref().expect("flash support requires wgpu"))),
             project_dirs,
         }
     }
 
-    fn start_search(&self) -> Option<(usize,usize)> {
+    /// Parses the search query and kicks off a search on the rayon pool.  If `refine` is set and
+    /// there are results on screen, only those results are searched and the current level gets
+    /// pushed onto the refinement chain.
+    fn start_search(&mut self, refine: bool) -> Option<(usize,usize)> {
         let state = self.ui_state.clone();
         let post_db = self.post_db.clone();
-        let parse_tag_fn = |s| self.tag_db.search_wildcard(s).map(|tag| tag.id).collect::<Vec<u32>>();
-        let (query, sort_order) = match vince621_core::search::e6_posts::parse_query_and_sort_order(parse_tag_fn, &self.search_query) {
+        let related_tags = self.related_tags.clone();
+        let tag_db = self.tag_db.clone();
+        let db_generation = self.db_generation.clone();
+        let generation = db_generation.load(Ordering::SeqCst);
+        let (query, sort_order) = match search::parse_query(&self.tag_db, &self.search_query) {
             Ok(x) => x,
-            Err(e) => {
-                let (start_pos, end_pos) = e.get_range(&self.search_query);
+            Err((range, reason)) => {
                 // cursor positions expect character offsets, not byte offsets, so we need to
                 // convert them.
-                let start_pos = self.search_query[..start_pos].chars().count();
-                let end_pos = self.search_query[..end_pos].chars().count();
+                let start_pos = text_offsets::byte_to_char(&self.search_query, range.start);
+                let end_pos = text_offsets::byte_to_char(&self.search_query, range.end);
 
-                *state.lock().unwrap() = UiState::ShowText(e.into_reason());
+                // leave whatever results are on screen alone -- the error gets shown under the
+                // search box instead.
+                self.search_error = Some((range, reason));
                 return Some((start_pos, end_pos));
             }
         };
+        self.search_error = None;
+        self.autocompleter.record_usage(&self.search_query);
+        // every query has a sort order, but when refining only an explicit `order:` should
+        // override the order the parent results are already in
+        let has_explicit_order = self.search_query.split_whitespace().any(|term| term.starts_with("order:"));
+        let base = if refine {
+            match *state.lock().unwrap() {
+                UiState::ShowPosts(ref results, idx) => Some((results.clone(), idx)),
+                _ => None,
+            }
+        } else {
+            None
+        };
+        let base = match base {
+            Some((results, idx)) => {
+                self.refinements.push(Refinement {
+                    query: std::mem::replace(&mut self.active_query, self.search_query.clone()),
+                    results: results.clone(),
+                    idx,
+                });
+                Some(results)
+            },
+            None => {
+                self.refinements.clear();
+                self.active_query = self.search_query.clone();
+                None
+            },
+        };
         rayon::spawn(move || {
+            // results are indices into the post database this started with, so if it's been
+            // swapped out since, they're meaningless.  checked under the lock, since
+            // `swap_databases` bumps the generation before it takes it.
+            let is_current = || db_generation.load(Ordering::SeqCst) == generation;
+            let report = |new_state: UiState| {
+                let mut state = state.lock().unwrap();
+                if is_current() {
+                    *state = new_state;
+                }
+            };
+            if let Some(base) = base {
+                // filtering keeps the parent results' order, so they only need re-sorting if the
+                // refining query asked for a different one.
+                let posts = post_db.get_all();
+                let searcher = rayon_progress::ProgressAdaptor::new(base.as_slice());
+                report(UiState::Searching(searcher.items_processed(), searcher.len()));
+                let t1 = Instant::now();
+                let mut results = searcher.filter(|idx| query.validate(&posts[**idx])).copied().collect::<Vec<_>>();
+                println!("refining search took {:?}", t1.elapsed());
+                if has_explicit_order {
+                    search::sort_results(posts, &mut results, sort_order);
+                }
+                if is_current() {
+                    related_tags.recompute(tag_db.clone(), post_db.clone(), results.clone());
+                }
+                report(UiState::ShowPosts(results, 0));
+                return;
+            }
             let searcher = rayon_progress::ProgressAdaptor::new(post_db.get_all());
-            *state.lock().unwrap() = UiState::Searching(searcher.items_processed(), searcher.len());
+            report(UiState::Searching(searcher.items_processed(), searcher.len()));
             let t1 = Instant::now();
             let mut results = searcher.enumerate().filter(|(_, post)| query.validate(post)).map(|(idx, _)| idx).collect::<Vec<_>>();
             let elapsed = t1.elapsed();
             println!("search took {:?}", elapsed);
             let t2 = Instant::now();
-            match sort_order {
-                SortOrder::DateAscending => {
-                    // post database is already sorted by that -- we don't need to do anything
-                },
-                SortOrder::Date => {
-                    results.reverse();
-                },
-                SortOrder::Random => {
-                    results.shuffle(&mut rand::thread_rng());
-                }
-                other => {
-                    let posts = post_db.get_all();
-                    macro_rules! match_arms {
-                        ($match_on:ident, $results: ident, $posts: ident, $($order: ident => post.$field:ident),*) => {
-                            paste!(
-                            match $match_on {
-                                $(
-                                    SortOrder::$order => $results.par_sort_unstable_by(|a,b| $posts[*b].$field.cmp(&$posts[*a].$field)),
-                                    SortOrder::[<$order Ascending>] => $results.par_sort_unstable_by(|a,b| $posts[*a].$field.cmp(&posts[*b].$field)),
-                                )*
-                                SortOrder::Date | SortOrder::DateAscending | SortOrder::Random => unreachable!()
-                            }
-                            )
-                        }
-                    }
-                    match_arms!(other, results, posts, Score => post.score, FavCount => post.fav_count);
-
-                },
-            }
+            search::sort_results(post_db.get_all(), &mut results, sort_order);
             println!("sort took {:?}", t2.elapsed());
-            *state.lock().unwrap() = UiState::ShowPosts(results, 0);
+            if is_current() {
+                related_tags.recompute(tag_db.clone(), post_db.clone(), results.clone());
+            }
+            report(UiState::ShowPosts(results, 0));
         });
         None
     }
 
-    fn show_settings_dialog(mut settings: MutexGuard<'_, Settings>, ui: &mut Ui) {
+    /// Swaps in freshly loaded databases, e.g. after `vince621 import`.  Results are indices into
+    /// the old post database, so they have to go; the autocomplete popup keeps its own snapshot of
+    /// the old tag database and stays usable until the next keystroke.
+    fn swap_databases(&mut self, tag_db: TagAndImplicationDatabase, post_db: PostDatabase) {
+        self.db_generation.fetch_add(1, Ordering::SeqCst);
+        self.tag_db = Arc::new(tag_db);
+        self.post_db = Arc::new(post_db);
+        self.autocompleter.set_tag_db(self.tag_db.clone());
+        self.refinements.clear();
+        self.search_error = None;
+        self.flashplayer = None;
+        *self.ui_state.lock().unwrap() = UiState::ShowText("Databases reloaded".into());
+    }
+
+    /// Replaces the results with just the post with id `post_id`, as if it had been searched for.
+    fn show_single_post(&mut self, post_id: u32) {
+        let posts = self.post_db.get_all();
+        let Ok(post_idx) = posts.binary_search_by_key(&post_id, |p| p.id.get()) else {
+            *self.ui_state.lock().unwrap() = UiState::ShowText(format!("Post {} isn't in the database", post_id));
+            return;
+        };
+        self.search_query = format!("id:{}", post_id);
+        self.active_query = self.search_query.clone();
+        self.refinements.clear();
+        self.search_error = None;
+        self.flashplayer = None;
+        self.related_tags.recompute(self.tag_db.clone(), self.post_db.clone(), vec![post_idx]);
+        *self.ui_state.lock().unwrap() = UiState::ShowPosts(vec![post_idx], 0);
+    }
+
+    /// Storage for the Flash movie of post `post_id`, wherever the settings say saves should go.
+    fn flash_storage(&self, post_id: u32) -> Box<dyn StorageBackend> {
+        let data_dir = self.project_dirs.data_dir();
+        let storage = self.settings.lock().unwrap().flash_storage;
+        match storage.open(data_dir, post_id) {
+            Ok(storage) => storage,
+            Err(e) => {
+                println!("couldn't open flash save database, saving to files instead: {}", e);
+                Box::new(DiskStorageBackend::new(data_dir.join("flash_saves").join(post_id.to_string())))
+            },
+        }
+    }
+
+    /// Pops the refinement chain back to level `level`, restoring the results that were on screen
+    /// at that point.
+    fn pop_refinement(&mut self, level: usize) {
+        let mut popped = self.refinements.drain(level..);
+        let Some(Refinement { query, results, idx }) = popped.next() else { return };
+        drop(popped);
+        self.flashplayer = None;
+        self.search_query = query.clone();
+        self.active_query = query;
+        self.related_tags.recompute(self.tag_db.clone(), self.post_db.clone(), results.clone());
+        *self.ui_state.lock().unwrap() = UiState::ShowPosts(results, idx);
+    }
+
+    fn show_settings_dialog(mut settings: MutexGuard<'_, Settings>, ui: &mut Ui, data_dir: &Path) {
+        if ui.checkbox(&mut settings.personalized_autocomplete, "Rank tags I search for often first in autocomplete").changed() {
+            save_setting(data_dir, "personalized_autocomplete", settings.personalized_autocomplete);
+        }
+        ui.horizontal(|ui| {
+            ui.label("Autocomplete suggestions per tag category:");
+            ui.add(egui::DragValue::new(&mut settings.autocomplete_per_category).clamp_range(1..=50));
+        });
+        ui.horizontal(|ui| {
+            ui.label("Store Flash saves as:");
+            let files = ui.radio_value(&mut settings.flash_storage, FlashStorage::Files, "separate files");
+            let sqlite = ui.radio_value(&mut settings.flash_storage, FlashStorage::Sqlite, "one SQLite database");
+            if files.changed() || sqlite.changed() {
+                settings.flash_storage.save(data_dir);
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("Flash volume:");
+            let volume = ui.add_enabled(!settings.flash_muted, egui::Slider::new(&mut settings.flash_volume, 0.0..=1.0));
+            // once a drag is over rather than on every frame of it
+            if volume.drag_stopped() || (volume.changed() && !volume.dragged()) {
+                save_setting(data_dir, "flash_volume", settings.flash_volume);
+            }
+            if ui.checkbox(&mut settings.flash_muted, "Mute").changed() {
+                save_setting(data_dir, "flash_muted", settings.flash_muted);
+            }
+        });
         if ui.input(|i| i.viewport().close_requested()) {
             settings.settings_dialog_is_open=false;
         }
@@ -146,9 +382,44 @@ impl App {
 
 impl eframe::App for App {
     fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
+        let reloaded = self.reloaded_databases.lock().unwrap().take();
+        match reloaded {
+            Some(Ok((tag_db, post_db))) => self.swap_databases(tag_db, post_db),
+            Some(Err(e)) => *self.ui_state.lock().unwrap() = UiState::ShowText(e),
+            None => {},
+        }
+        // a fullscreen movie gets the whole window, apart from its own controls
+        let flash_fullscreen = self.flashplayer.is_some() && ctx.input(|i| i.viewport().fullscreen.unwrap_or(false));
         {
             let mut settings = self.settings.lock().unwrap();
-            TopBottomPanel::top("menu").show(ctx, |ui| egui::menu::bar(ui, |ui| {
+            self.autocompleter.personalize = settings.personalized_autocomplete;
+            self.autocompleter.per_category_limit = settings.autocomplete_per_category;
+            self.flash_volume.set(if settings.flash_muted { 0.0 } else { settings.flash_volume });
+            let has_results = matches!(*self.ui_state.lock().unwrap(), UiState::ShowPosts(..));
+            TopBottomPanel::top("menu").show_animated(ctx, !flash_fullscreen, |ui| egui::menu::bar(ui, |ui| {
+                ui.menu_button("File", |ui| {
+                    if ui.add_enabled(has_results, egui::Button::new("Export results...")).clicked() {
+                        if let UiState::ShowPosts(ref results, _) = *self.ui_state.lock().unwrap() {
+                            self.export_dialog = Some(ExportDialog::new(self.project_dirs.data_dir(), self.tag_db.clone(), self.post_db.clone(), results, &settings.user_blacklist));
+                        }
+                        ui.close_menu();
+                    }
+                    let searching = matches!(*self.ui_state.lock().unwrap(), UiState::Searching(..));
+                    if ui.add_enabled(!searching, egui::Button::new("Reload databases")).clicked() {
+                        let reloaded = self.reloaded_databases.clone();
+                        let cache_dir = self.project_dirs.cache_dir().to_path_buf();
+                        let ctx = ctx.clone();
+                        rayon::spawn(move || {
+                            *reloaded.lock().unwrap() = Some(load_databases(&cache_dir));
+                            ctx.request_repaint();
+                        });
+                        ui.close_menu();
+                    }
+                    if ui.button("Flash saves...").clicked() {
+                        self.save_manager = Some(SaveManager::new(self.project_dirs.data_dir(), settings.flash_storage));
+                        ui.close_menu();
+                    }
+                });
                 ui.menu_button("Settings", |ui| {
                     if ui.button("Settings").clicked() {
                         settings.settings_dialog_is_open=true;
@@ -157,19 +428,70 @@ impl eframe::App for App {
             }));
             if settings.settings_dialog_is_open {
                 let settings = self.settings.clone();
+                let data_dir = self.project_dirs.data_dir().to_path_buf();
                 ctx.show_viewport_deferred(egui::ViewportId(Id::new("settings_dialog")),
                 ViewportBuilder::default(),
                 move |ctx, _class| {
                     CentralPanel::default().show(ctx, |ui| {
-                        App::show_settings_dialog(settings.lock().unwrap(), ui);
+                        App::show_settings_dialog(settings.lock().unwrap(), ui, &data_dir);
                     });
                 });
             }
+            if let Some(ref mut dialog) = self.export_dialog {
+                let still_open = match *self.ui_state.lock().unwrap() {
+                    UiState::ShowPosts(..) => dialog.show(ctx),
+                    _ => false,
+                };
+                if !still_open {
+                    self.export_dialog = None;
+                }
+            }
+        }
+        if let Some(ref mut manager) = self.save_manager {
+            let (still_open, open_post) = manager.show(ctx, self.post_db.get_all());
+            if !still_open {
+                self.save_manager = None;
+            }
+            if let Some(post_id) = open_post {
+                self.show_single_post(post_id);
+            }
         }
-        TopBottomPanel::top("search").show(ctx, |ui| ui.horizontal(|mut ui| {
+        TopBottomPanel::top("search").show_animated(ctx, !flash_fullscreen, |ui| ui.horizontal(|mut ui| {
             let id = ui.make_persistent_id("search box");
             let initial_cursor_range = TextEditState::load(ui.ctx(), id).and_then(|state| state.cursor.char_range());
-            let mut textbox = TextEdit::singleline(&mut self.search_query).id(id).show(&mut ui);
+            let popup_id = Id::new("tag_autocomplete_dropdown");
+            let popup_open = ui.memory(|mem| mem.is_popup_open(popup_id) && mem.has_focus(id));
+            let key_range = if popup_open {
+                self.autocompleter.handle_keys(ui, &mut self.search_query)
+            } else {
+                None
+            };
+            let tag_db = &self.tag_db;
+            let highlighted_error = self.search_error.as_ref().map(|(range, _)| range.clone());
+            let mut layouter = |ui: &Ui, text: &str, wrap_width: f32| {
+                let mut job = highlight::highlight_query(ui, tag_db, text, highlighted_error.clone());
+                job.wrap.max_width = wrap_width;
+                ui.fonts(|fonts| fonts.layout_job(job))
+            };
+            let mut textbox = TextEdit::singleline(&mut self.search_query).id(id).layouter(&mut layouter).show(&mut ui);
+            if textbox.response.changed() {
+                self.search_error = None;
+            }
+            if ui.memory(|mem| mem.is_popup_open(popup_id)) {
+                // keep tab and escape from moving focus off the search box so handle_keys() gets
+                // to see them next frame.
+                ui.memory_mut(|mem| mem.set_focus_lock_filter(id, EventFilter {
+                    tab: true,
+                    horizontal_arrows: true,
+                    vertical_arrows: true,
+                    escape: true,
+                }));
+            }
+            if let Some((_, ref reason)) = self.search_error {
+                egui::show_tooltip_for(ui.ctx(), id.with("error"), &textbox.response.rect, |ui| {
+                    ui.colored_label(ui.visuals().error_fg_color, reason);
+                });
+            }
             let mut error_range = None;
             if textbox.response.has_focus() {//&& !self.search_query.ends_with('}') && !self.search_query.ends_with(' ') {
                 // TODO predicate this also on whether the text was modified and/or the cursor moved.
@@ -177,7 +499,7 @@ impl eframe::App for App {
                     if textbox.response.changed() || initial_cursor_range != Some(pos) {
                         println!("rerunning autocompleter");
                         if self.autocompleter.do_autocomplete(&self.search_query, pos.primary.index) {
-                            ui.memory_mut(|mem| mem.open_popup(Id::new("tag_autocomplete_dropdown")));
+                            ui.memory_mut(|mem| mem.open_popup(popup_id));
                         } else {
                             ui.memory_mut(|mem| mem.close_popup());
                         }
@@ -188,17 +510,26 @@ impl eframe::App for App {
 
             } else if textbox.response.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter)) { //eewwwwww
                 self.flashplayer=None;
-                error_range = self.start_search();
+                // shift+enter searches within the current results
+                let refine = ui.input(|i| i.modifiers.shift);
+                error_range = self.start_search(refine);
             }
             if ui.button("Search").clicked() {
                 self.flashplayer=None;
-                error_range = self.start_search();
+                error_range = self.start_search(false);
+            }
+            let has_results = matches!(*self.ui_state.lock().unwrap(), UiState::ShowPosts(..));
+            if ui.add_enabled(has_results, egui::Button::new("Search within results")).clicked() {
+                self.flashplayer=None;
+                error_range = self.start_search(true);
             }
 
             let range = if let Some((start, end)) = error_range {
                 Some(CCursorRange::two(CCursor::new(start), CCursor::new(end)))
+            } else if key_range.is_some() {
+                key_range
             } else {
-                popup_below_widget(&ui, Id::new("tag_autocomplete_dropdown"), &textbox.response, |ui| self.autocompleter.show_autocomplete_ui(&mut self.search_query, ui)).flatten()
+                popup_below_widget(&ui, popup_id, &textbox.response, |ui| self.autocompleter.show_autocomplete_ui(&mut self.search_query, ui)).flatten()
             };
 
             if let Some(range) = range {
@@ -208,6 +539,78 @@ impl eframe::App for App {
             }
         }));
 
+        if !self.refinements.is_empty() && !flash_fullscreen {
+            TopBottomPanel::top("refinements").show(ctx, |ui| ui.horizontal(|ui| {
+                let mut pop_to = None;
+                for (level, refinement) in self.refinements.iter().enumerate() {
+                    let label = if refinement.query.is_empty() { "(all posts)" } else { refinement.query.as_str() };
+                    if ui.link(label).on_hover_text(format!("{} results", refinement.results.len())).clicked() {
+                        pop_to = Some(level);
+                    }
+                    ui.label(">");
+                }
+                ui.strong(&self.active_query);
+                if ui.small_button("Back").clicked() {
+                    pop_to = Some(self.refinements.len()-1);
+                }
+                if let Some(level) = pop_to {
+                    self.pop_refinement(level);
+                }
+            }));
+        }
+
+        let current_post = match *self.ui_state.lock().unwrap() {
+            UiState::ShowPosts(ref results, idx) => results.get(idx).copied(),
+            _ => None,
+        };
+        if let Some(post_idx) = current_post {
+            let action = egui::SidePanel::left("post_info").show_animated(ctx, self.show_post_info && !flash_fullscreen, |ui| {
+                post_info::show_post_info(ui, &self.tag_db, &self.post_db.get_all()[post_idx])
+            }).and_then(|response| response.inner);
+            match action {
+                Some(TagAction::NewSearch(tag)) => {
+                    self.search_query = tag;
+                    self.flashplayer = None;
+                    self.start_search(false);
+                },
+                Some(TagAction::Append(tag)) => {
+                    if !self.search_query.is_empty() && !self.search_query.ends_with(' ') {
+                        self.search_query.push(' ');
+                    }
+                    self.search_query.push_str(&tag);
+                },
+                None => {},
+            }
+        }
+
+        if self.show_flash_console && !flash_fullscreen {
+            egui::TopBottomPanel::bottom("flash_console").resizable(true).show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.heading("Flash console");
+                    ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
+                        if ui.button("✖").clicked() {
+                            self.show_flash_console = false;
+                        }
+                    });
+                });
+                self.flash_console.lock().unwrap().show(ui, self.project_dirs.data_dir());
+            });
+        }
+
+        if matches!(*self.ui_state.lock().unwrap(), UiState::ShowPosts(..)) {
+            egui::SidePanel::right("related_tags").show_animated(ctx, !flash_fullscreen, |ui| {
+                ui.heading("Related tags");
+                self.related_tags.show(ui, &mut self.search_query);
+            });
+        }
+
+        {
+            let mut flash_ui = self.flash_ui.lock().unwrap();
+            flash_ui.observe_input(ctx);
+            flash_ui.show_dialogs(ctx);
+        }
+        self.flash_executor.poll_all();
+
         CentralPanel::default().show(ctx, |ui| {
             match *self.ui_state.lock().unwrap() {
                 UiState::ShowText(ref s) => {
@@ -223,43 +626,113 @@ impl eframe::App for App {
                         ui.label("No results");
                     } else {
                         if !ctx.wants_keyboard_input() {
-                            if ui.input(|i| i.key_pressed(Key::ArrowLeft)) && *idx > 0 {
+                            let (left, right, shift) = ui.input(|i| (i.key_pressed(Key::ArrowLeft), i.key_pressed(Key::ArrowRight), i.modifiers.shift));
+                            if shift && (left || right) {
+                                // skip straight to the previous/next Flash movie
+                                if let Some(swf_idx) = search::next_swf(self.post_db.get_all(), results, *idx, right) {
+                                    *idx = swf_idx;
+                                    self.flashplayer=None;
+                                }
+                            } else if left && *idx > 0 {
                                 *idx -= 1;
                                 self.flashplayer=None;
-                            } else if ui.input(|i| i.key_pressed(Key::ArrowRight)) && *idx < results.len()-1 {
+                            } else if right && *idx < results.len()-1 {
                                 *idx += 1;
                                 self.flashplayer=None;
                             }
                         }
+                        let mut jump_to = None;
                         let post_idx = results[*idx];
                         let posts = self.post_db.get_all();
                         let post = &posts[post_idx];
-                        ui.label(format!("Showing result {} of {} (id {})", *idx+1, results.len(), post.id));
+                        ui.horizontal(|ui| {
+                            ui.label(format!("Showing result {} of {} (id {})", *idx+1, results.len(), post.id));
+                            ui.toggle_value(&mut self.show_post_info, "Info");
+                            if matches!(post.file_ext, FileExtension::SWF) {
+                                ui.toggle_value(&mut self.show_flash_console, "Console");
+                                // only worked out on click, since it can mean scanning every result
+                                if ui.button("⏴ Flash").on_hover_text("Previous Flash movie (Shift+←)").clicked() {
+                                    jump_to = search::next_swf(posts, results, *idx, false);
+                                }
+                                if ui.button("Flash ⏵").on_hover_text("Next Flash movie (Shift+→)").clicked() {
+                                    jump_to = search::next_swf(posts, results, *idx, true);
+                                }
+                            }
+                        });
                         
                         match post.file_ext {
                             FileExtension::SWF => {
-                                if let Some(player) = self.flashplayer.as_mut() {
-                                    player.show(ui);
+                                let flash_info = self.flash_info.filter(|(id, _)| *id == post.id.get()).map(|(_, info)| info);
+                                if let Some(info) = flash_info {
+                                    ui.label(info.to_string());
+                                }
+                                let flash_error = self.flash_error.as_ref().filter(|(id, _)| *id == post.id.get());
+                                if let Some((_, message)) = flash_error {
+                                    if show_flash_error(ui, post.id.get(), message) {
+                                        ctx.forget_image(&post.url(ImageResolution::Full));
+                                        self.flash_error = None;
+                                    }
+                                } else if let Some(player) = self.flashplayer.as_mut() {
+                                    let mut prefs = self.flash_prefs.get(post.id.get());
+                                    let old_prefs = prefs;
+                                    let mut action = controls::show_control_bar(ui, player, &mut prefs);
+                                    // keep the player the same shape as the stage, so the movie isn't letterboxed
+                                    // or stretched by default
+                                    let size = flash_info.map(|info| info.fit(ui.available_size())).unwrap_or(ui.available_size());
+                                    let response = ui.allocate_ui(size, |ui| player.show(ui)).response.interact(Sense::click());
+                                    response.context_menu(|ui| {
+                                        if let Some(a) = controls::show_context_menu(ui, player, &mut prefs) {
+                                            action = Some(a);
+                                        }
+                                    });
+                                    // only over the movie itself, not the controls around it
+                                    if response.hovered() {
+                                        self.flash_ui.lock().unwrap().apply_cursor(ctx);
+                                    }
+                                    if prefs != old_prefs {
+                                        self.flash_prefs.set(post.id.get(), prefs);
+                                    }
+                                    if let Some(ControlAction::Restart) = action {
+                                        self.flashplayer = None;
+                                    }
                                 } else {
                                     match ctx.try_load_bytes(&post.url(ImageResolution::Full)) {
                                         Ok(BytesPoll::Pending { .. }) => {
                                             ui.spinner();
                                         },
                                         Ok(BytesPoll::Ready { bytes, .. }) => {
-                                            let movie = SwfMovie::from_data(&bytes, post.url(ImageResolution::Full), None).expect("error loading movie");
-                                            let builder = PlayerBuilder::new()
-                                                .with_movie(movie)
-                                                .with_storage(Box::new(DiskStorageBackend::new(self
-                                                                                               .project_dirs
-                                                                                               .data_dir()
-                                                                                               .join("flash_saves")
-                                                                                               .join(post.id.get().to_string())
-                                                                                               )))
-                                                .with_video(ruffle_video_software::backend::SoftwareVideoBackend::new())
-                                                ;
-                                            self.flashplayer = Some(EguiRufflePlayer::new(builder, frame.wgpu_render_state().expect("flashplayer requires wgpu"), self.ruffle_descriptors.clone(), (1,1)).expect("Could not create flashplayer"));
+                                            match SwfMovie::from_data(&bytes, post.url(ImageResolution::Full), None) {
+                                                Ok(movie) => {
+                                                    let info = SwfInfo::new(&movie);
+                                                    self.flash_info = Some((post.id.get(), info));
+                                                    let builder = PlayerBuilder::new()
+                                                        .with_movie(movie)
+                                                        .with_storage(self.flash_storage(post.id.get()))
+                                                        .with_video(ruffle_video_software::backend::SoftwareVideoBackend::new())
+                                                        .with_ui(Box::new(EguiUiBackend::new(ctx.clone(), self.flash_ui.clone())))
+                                                        .with_log(ConsoleLogBackend::new(self.flash_console.clone()))
+                                                        ;
+                                                    self.flash_console.lock().unwrap().start(post.id.get());
+                                                    let mut builder = ruffle_util::audio::with_audio(builder, self.flash_volume.clone());
+                                                    match url::Url::parse(&post.url(ImageResolution::Full)) {
+                                                        Ok(base_url) => builder = builder.with_navigator(SandboxedNavigator::new(base_url, self.flash_executor.clone(), ctx.clone(), self.flash_ui.clone(), self.project_dirs.cache_dir().join("flash_assets"))),
+                                                        Err(e) => tracing::warn!("Couldn't parse post URL, movie won't be able to load anything: {}", e),
+                                                    }
+                                                    match EguiRufflePlayer::new(builder, frame.wgpu_render_state().expect("flashplayer requires wgpu"), self.ruffle_descriptors.clone(), info.stage_size()) {
+                                                        Ok(player) => {
+                                                            self.flash_prefs.get(post.id.get()).apply(&player);
+                                                            self.flashplayer = Some(player);
+                                                        },
+                                                        Err(e) => self.flash_error = Some((post.id.get(), format!("Couldn't start the Flash player: {:?}", e))),
+                                                    }
+                                                },
+                                                Err(e) => {
+                                                    self.flash_error = Some((post.id.get(), format!("This doesn't look like a Flash movie: {}", e)));
+                                                },
+                                            }
                                         },
-                                        Err(_) => {
+                                        Err(e) => {
+                                            self.flash_error = Some((post.id.get(), format!("Couldn't download the movie: {}", e)));
                                         },
                                     }
                                 }
@@ -275,26 +748,72 @@ impl eframe::App for App {
                         for post_idx in results[next_idx..last_idx].iter() {
                             let _ = ctx.try_load_bytes(&posts[*post_idx].url(ImageResolution::Sample));
                         }
+
+                        if let Some(swf_idx) = jump_to {
+                            *idx = swf_idx;
+                            self.flashplayer=None;
+                            ctx.request_repaint();
+                        }
                     }
                 },
             }
         });
+        // hold off while a button is down, so dragging the volume slider writes once, on release
+        if !ctx.input(|i| i.pointer.any_down()) {
+            self.flash_prefs.flush();
+        }
     }
 }
 
+/// Shown in place of a Flash movie that couldn't be played.  Returns true if the user wants to try
+/// again.
+fn show_flash_error(ui: &mut Ui, post_id: u32, message: &str) -> bool {
+    let mut retry = false;
+    ui.vertical_centered(|ui| {
+        egui::Frame::group(ui.style()).show(ui, |ui| {
+            ui.heading("This movie can't be played");
+            ui.label(RichText::new(message).color(ui.visuals().error_fg_color));
+            ui.horizontal(|ui| {
+                retry = ui.button("Retry").clicked();
+                ui.hyperlink_to("Open in browser", format!("https://e621.net/posts/{}", post_id));
+            });
+        });
+    });
+    retry
+}
+
+/// Loads the tag and post databases out of `dir`.
+fn load_databases(dir: &Path) -> Result<(TagAndImplicationDatabase, PostDatabase), String> {
+    let (tag_db, post_db) = rayon::join(
+        || {
+            let mut f = std::io::BufReader::new(std::fs::File::open(dir.join("tags.v621")).map_err(|e| format!("Error opening tags file: {}", e))?);
+            let hdr = vince621_serialization::tags::read_tag_header(&mut f).map_err(|e| format!("error reading tag header: {:?}", e))?;
+            vince621_serialization::tags::deserialize_tag_and_implication_database(hdr, &mut f).map_err(|e| format!("error reading tags: {:?}", e))
+        },
+        || {
+            let mut f = std::io::BufReader::new(std::fs::File::open(dir.join("posts.v621")).map_err(|e| format!("Error opening posts file: {}", e))?);
+            vince621_serialization::deserialize_post_database(&mut f).map_err(|e| format!("error reading posts: {:?}", e))
+        }
+    );
+    Ok((tag_db?, post_db?))
+}
+
 fn main() -> Result<(), eframe::Error> {
     let Some(proj_dirs) = ProjectDirs::from("blue", "spacestation", "Vince621") else {
         println!("Couldn't decide where to put config directories!");
         return Ok(())
     };
-    let (tag_db, post_db) = rayon::join(
-        || {
-            let mut f = std::io::BufReader::new(std::fs::File::open(proj_dirs.cache_dir().join("tags.v621")).expect("Error opening tags file"));
-            let hdr = vince621_serialization::tags::read_tag_header(&mut f).expect("error reading tag header");
-            vince621_serialization::tags::deserialize_tag_and_implication_database(hdr, &mut f).unwrap()
-        },
-        || vince621_serialization::deserialize_post_database(&mut std::io::BufReader::new(std::fs::File::open(proj_dirs.cache_dir().join("posts.v621")).unwrap())).unwrap()
-    );
+    let args = std::env::args().collect::<Vec<_>>();
+    if let Some(code) = cli::run(&args, &proj_dirs) {
+        std::process::exit(code);
+    }
+    let (tag_db, post_db) = match load_databases(proj_dirs.cache_dir()) {
+        Ok(x) => x,
+        Err(e) => {
+            println!("{}", e);
+            return Ok(())
+        }
+    };
     let image_dir = proj_dirs.cache_dir().join("images");
     match std::fs::create_dir(&image_dir) {
         Ok(()) => {
@@ -0,0 +1,61 @@
+//! The metatags the search box understands, for autocompletion and highlighting.
+
+use vince621_core::search::e6_posts::SortOrder;
+
+pub struct Metatag {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// (value, description) pairs to suggest after the colon.  Empty for numeric metatags, where
+    /// `description` says what the value should look like instead.
+    pub values: &'static [(&'static str, &'static str)],
+}
+
+/// The `order:` keyword for each sort order.  This is a match rather than a table so adding a
+/// `SortOrder` variant won't compile until it gets a keyword here too.
+const fn sort_order_value(order: SortOrder) -> (&'static str, &'static str) {
+    match order {
+        SortOrder::Date => ("id_desc", "newest first"),
+        SortOrder::DateAscending => ("id", "oldest first"),
+        SortOrder::Score => ("score", "highest score first"),
+        SortOrder::ScoreAscending => ("score_asc", "lowest score first"),
+        SortOrder::FavCount => ("favcount", "most favorited first"),
+        SortOrder::FavCountAscending => ("favcount_asc", "least favorited first"),
+        SortOrder::Random => ("random", "shuffled"),
+    }
+}
+
+const SORT_ORDER_VALUES: &[(&str, &str)] = &[
+    sort_order_value(SortOrder::Date),
+    sort_order_value(SortOrder::DateAscending),
+    sort_order_value(SortOrder::Score),
+    sort_order_value(SortOrder::ScoreAscending),
+    sort_order_value(SortOrder::FavCount),
+    sort_order_value(SortOrder::FavCountAscending),
+    sort_order_value(SortOrder::Random),
+];
+
+pub const METATAGS: &[Metatag] = &[
+    Metatag { name: "order", description: "sort results", values: SORT_ORDER_VALUES },
+    Metatag { name: "rating", description: "content rating", values: &[
+        ("s", "safe"),
+        ("q", "questionable"),
+        ("e", "explicit"),
+    ] },
+    Metatag { name: "type", description: "file type", values: &[
+        ("jpg", "JPEG image"),
+        ("png", "PNG image"),
+        ("gif", "GIF image or animation"),
+        ("webm", "video"),
+        ("swf", "Flash"),
+    ] },
+    Metatag { name: "score", description: "e.g. score:>100 or score:10..50", values: &[] },
+    Metatag { name: "favcount", description: "e.g. favcount:>=50", values: &[] },
+    Metatag { name: "id", description: "e.g. id:<1000 or id:100..200", values: &[] },
+    Metatag { name: "width", description: "e.g. width:>=1920", values: &[] },
+    Metatag { name: "height", description: "e.g. height:>=1080", values: &[] },
+    Metatag { name: "filesize", description: "e.g. filesize:<1MB", values: &[] },
+];
+
+pub fn get(name: &str) -> Option<&'static Metatag> {
+    METATAGS.iter().find(|m| m.name == name)
+}
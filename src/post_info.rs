@@ -0,0 +1,96 @@
+use egui::{CollapsingHeader, Grid, RichText, Ui};
+use vince621_core::db::{posts::Post, tags::TagAndImplicationDatabase};
+
+use crate::autocomplete::{tag_category_color, CATEGORY_ORDER};
+
+/// What the user asked for by clicking a tag in the post info panel.
+pub enum TagAction {
+    /// Throw away the current query and search for just this tag.
+    NewSearch(String),
+    /// Add this tag to the end of the current query.
+    Append(String),
+}
+
+/// Formats a byte count for humans.
+pub(crate) fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len()-1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// Draws everything we know about `post`: tags grouped by category, then the rest of the metadata.
+///
+/// Clicking a tag starts a new search for it; shift-clicking appends it to the current query.
+pub fn show_post_info(ui: &mut Ui, tag_db: &TagAndImplicationDatabase, post: &Post) -> Option<TagAction> {
+    let mut action = None;
+    egui::ScrollArea::vertical().show(ui, |ui| {
+        Grid::new("post_metadata").num_columns(2).striped(true).show(ui, |ui| {
+            ui.label("ID");
+            ui.label(post.id.get().to_string());
+            ui.end_row();
+            ui.label("Rating");
+            ui.label(format!("{:?}", post.rating));
+            ui.end_row();
+            ui.label("Score");
+            ui.label(post.score.to_string());
+            ui.end_row();
+            ui.label("Favorites");
+            ui.label(post.fav_count.to_string());
+            ui.end_row();
+            ui.label("Dimensions");
+            ui.label(format!("{}x{}", post.width, post.height));
+            ui.end_row();
+            ui.label("File size");
+            ui.label(format_size(post.file_size as u64));
+            ui.end_row();
+            ui.label("Uploaded");
+            ui.label(post.created_at.to_string());
+            ui.end_row();
+        });
+
+        let tags = post.tags.iter().filter_map(|id| tag_db.get_by_id(*id)).collect::<Vec<_>>();
+        for (category, label) in CATEGORY_ORDER {
+            let mut in_category = tags.iter().filter(|tag| tag.category == category).peekable();
+            if in_category.peek().is_none() {
+                continue;
+            }
+            CollapsingHeader::new(label).id_source(("post_tags", label)).default_open(true).show(ui, |ui| {
+                for tag in in_category {
+                    let text = RichText::new(tag.name.as_str()).color(tag_category_color(tag.category));
+                    let response = ui.link(text).on_hover_text(format!("{} posts\nClick to search, shift-click to add to the current search", tag.post_count));
+                    if response.clicked() {
+                        let name = tag.name.as_str().to_owned();
+                        action = Some(if ui.input(|i| i.modifiers.shift) {
+                            TagAction::Append(name)
+                        } else {
+                            TagAction::NewSearch(name)
+                        });
+                    }
+                }
+            });
+        }
+
+        if !post.sources.is_empty() {
+            CollapsingHeader::new("Sources").default_open(true).show(ui, |ui| {
+                for source in post.sources.iter() {
+                    ui.hyperlink(source);
+                }
+            });
+        }
+
+        if !post.description.is_empty() {
+            CollapsingHeader::new("Description").default_open(false).show(ui, |ui| {
+                ui.label(post.description.as_str());
+            });
+        }
+    });
+    action
+}
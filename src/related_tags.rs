@@ -0,0 +1,112 @@
+use std::{collections::HashMap, sync::{atomic::{AtomicUsize, Ordering}, Arc, Mutex}};
+
+use egui::{CollapsingHeader, RichText, Ui};
+use rayon::iter::{IntoParallelRefIterator as _, ParallelIterator as _};
+use vince621_core::db::{posts::{Post, PostDatabase}, tags::{TagAndImplicationDatabase, TagCategory}};
+
+use crate::autocomplete::{tag_category_color, CATEGORY_ORDER};
+
+/// How many tags to list per category.
+const TAGS_PER_CATEGORY: usize = 15;
+
+struct Counts {
+    /// (category, heading, the category's most frequent tags with how many results have them),
+    /// in `CATEGORY_ORDER`.  Categories no result has a tag from are left out.
+    categories: Vec<(TagCategory, &'static str, Vec<(String, usize)>)>,
+    total: usize,
+}
+
+/// Splits `counts` (as returned by `count_tags`) up by category, keeping the top
+/// `TAGS_PER_CATEGORY` of each.
+fn group_by_category(tag_db: &TagAndImplicationDatabase, counts: &[(u32, usize)]) -> Vec<(TagCategory, &'static str, Vec<(String, usize)>)> {
+    let mut categories = CATEGORY_ORDER.map(|(category, heading)| (category, heading, Vec::new()));
+    for &(id, count) in counts {
+        let Some(tag) = tag_db.get_by_id(id) else { continue };
+        if let Some((_, _, entries)) = categories.iter_mut().find(|(c, _, _)| *c == tag.category) {
+            if entries.len() < TAGS_PER_CATEGORY {
+                entries.push((tag.name.as_str().to_owned(), count));
+            }
+        }
+    }
+    categories.into_iter().filter(|(_, _, entries)| !entries.is_empty()).collect()
+}
+
+/// Tag frequencies for the current result set.  Counting happens on the rayon pool, so the
+/// sidebar just shows a spinner until it's done.
+#[derive(Default)]
+pub struct RelatedTags {
+    counts: Mutex<Option<Counts>>,
+    generation: AtomicUsize,
+}
+
+/// Counts how many of `results` carry each tag, in parallel.
+pub fn count_tags(posts: &[Post], results: &[usize]) -> Vec<(u32, usize)> {
+    let counts = results.par_iter()
+        .fold(HashMap::new, |mut counts, idx| {
+            for tag in posts[*idx].tags.iter() {
+                *counts.entry(*tag).or_insert(0usize) += 1;
+            }
+            counts
+        })
+        .reduce(HashMap::new, |mut a, b| {
+            for (tag, count) in b {
+                *a.entry(tag).or_insert(0) += count;
+            }
+            a
+        });
+    let mut counts = counts.into_iter().collect::<Vec<_>>();
+    counts.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    counts
+}
+
+impl RelatedTags {
+    /// Throws away the current counts and starts counting tags for a new result set.  If this is
+    /// called again before the count finishes, the stale count gets dropped on the floor.
+    pub fn recompute(self: &Arc<Self>, tag_db: Arc<TagAndImplicationDatabase>, post_db: Arc<PostDatabase>, results: Vec<usize>) {
+        let generation = {
+            let mut counts = self.counts.lock().unwrap();
+            *counts = None;
+            self.generation.fetch_add(1, Ordering::SeqCst) + 1
+        };
+        let this = self.clone();
+        rayon::spawn(move || {
+            let tags = count_tags(post_db.get_all(), &results);
+            let categories = group_by_category(&tag_db, &tags);
+            let mut counts = this.counts.lock().unwrap();
+            if this.generation.load(Ordering::SeqCst) == generation {
+                *counts = Some(Counts { categories, total: results.len() });
+            }
+        });
+    }
+
+    /// Draws the sidebar.  Clicking a tag appends it to `search_query`; shift-clicking appends it
+    /// negated.
+    pub fn show(&self, ui: &mut Ui, search_query: &mut String) {
+        let counts = self.counts.lock().unwrap();
+        let Some(Counts { ref categories, total }) = *counts else {
+            ui.spinner();
+            return;
+        };
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            for (category, label, entries) in categories.iter() {
+                CollapsingHeader::new(*label).default_open(true).show(ui, |ui| {
+                    for (name, count) in entries.iter() {
+                        let percent = *count as f32 * 100.0 / total.max(1) as f32;
+                        let text = RichText::new(format!("{} ({}, {:.1}%)", name, count, percent)).color(tag_category_color(*category));
+                        let response = ui.link(text).on_hover_text("Click to add to the search, shift-click to exclude");
+                        if response.clicked() {
+                            let negate = ui.input(|i| i.modifiers.shift);
+                            if !search_query.is_empty() && !search_query.ends_with(' ') {
+                                search_query.push(' ');
+                            }
+                            if negate {
+                                search_query.push('-');
+                            }
+                            search_query.push_str(name);
+                        }
+                    }
+                });
+            }
+        });
+    }
+}
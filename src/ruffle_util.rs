@@ -1 +1,8 @@
+pub mod audio;
+pub mod controls;
+pub mod log_backend;
+pub mod navigator;
 pub mod storage;
+pub mod sqlite_storage;
+pub mod swf_info;
+pub mod ui_backend;
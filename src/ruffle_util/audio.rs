@@ -0,0 +1,97 @@
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use ruffle_core::backend::audio::{AudioBackend, AudioMixer, NullAudioBackend};
+use ruffle_core::impl_audio_mixer_backend;
+use ruffle_core::PlayerBuilder;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+/// The app-wide volume, applied on top of whatever volume the movie and its per-post preferences
+/// ask for.  Shared with the audio thread, so it's stored as the bits of an `f32`.
+#[derive(Clone)]
+pub struct GlobalVolume(Arc<AtomicU32>);
+
+impl Default for GlobalVolume {
+    fn default() -> Self {
+        GlobalVolume(Arc::new(AtomicU32::new(1.0f32.to_bits())))
+    }
+}
+
+impl GlobalVolume {
+    pub fn get(&self) -> f32 {
+        f32::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    /// 0 to 1.  Muting is just setting it to 0.
+    pub fn set(&self, volume: f32) {
+        self.0.store(volume.clamp(0.0, 1.0).to_bits(), Ordering::Relaxed);
+    }
+}
+
+/// Plays a movie's sound through the default output device.
+pub struct CpalAudioBackend {
+    stream: cpal::Stream,
+    mixer: AudioMixer,
+}
+
+impl CpalAudioBackend {
+    pub fn new(volume: GlobalVolume) -> Result<Self, String> {
+        let device = cpal::default_host().default_output_device().ok_or("no audio output device")?;
+        let config = device.default_output_config().map_err(|e| e.to_string())?;
+        let sample_format = config.sample_format();
+        let config = cpal::StreamConfig::from(config);
+        let mixer = AudioMixer::new(config.channels as u8, config.sample_rate.0);
+        let proxy = mixer.proxy();
+        let error_handler = |e| tracing::error!("Audio stream error: {}", e);
+
+        use cpal::SampleFormat;
+        let stream = match sample_format {
+            SampleFormat::F32 => device.build_output_stream(&config, move |buffer: &mut [f32], _| {
+                proxy.mix(buffer);
+                let v = volume.get();
+                buffer.iter_mut().for_each(|s| *s *= v);
+            }, error_handler, None),
+            SampleFormat::I16 => device.build_output_stream(&config, move |buffer: &mut [i16], _| {
+                proxy.mix(buffer);
+                let v = volume.get();
+                buffer.iter_mut().for_each(|s| *s = (*s as f32 * v) as i16);
+            }, error_handler, None),
+            SampleFormat::U16 => device.build_output_stream(&config, move |buffer: &mut [u16], _| {
+                proxy.mix(buffer);
+                // silence is the midpoint, not 0
+                let v = volume.get();
+                buffer.iter_mut().for_each(|s| *s = ((*s as f32 - 32768.0) * v + 32768.0) as u16);
+            }, error_handler, None),
+            other => return Err(format!("unsupported sample format {:?}", other)),
+        }.map_err(|e| e.to_string())?;
+        stream.play().map_err(|e| e.to_string())?;
+        Ok(CpalAudioBackend { stream, mixer })
+    }
+}
+
+impl AudioBackend for CpalAudioBackend {
+    impl_audio_mixer_backend!(mixer);
+
+    fn play(&mut self) {
+        if let Err(e) = self.stream.play() {
+            tracing::error!("Unable to resume audio stream: {}", e);
+        }
+    }
+
+    fn pause(&mut self) {
+        if let Err(e) = self.stream.pause() {
+            tracing::error!("Unable to pause audio stream: {}", e);
+        }
+    }
+}
+
+/// Adds sound to `builder`, or silence if there's nothing to play it on (no sound card, headless
+/// CI, ...).
+pub fn with_audio(builder: PlayerBuilder, volume: GlobalVolume) -> PlayerBuilder {
+    match CpalAudioBackend::new(volume) {
+        Ok(audio) => builder.with_audio(audio),
+        Err(e) => {
+            tracing::warn!("No audio output, Flash movies will be silent: {}", e);
+            builder.with_audio(NullAudioBackend::new())
+        },
+    }
+}
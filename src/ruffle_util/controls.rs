@@ -0,0 +1,253 @@
+//! The control bar and context menu for the Flash player, and the per-post preferences they edit.
+
+use egui::{Context, Slider, Ui, ViewportCommand};
+use egui_ruffle::EguiRufflePlayer;
+use ruffle_core::{config::Letterbox, Player, StageQuality, StageScaleMode};
+use std::{collections::HashMap, path::PathBuf};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Quality {
+    Low,
+    Medium,
+    High,
+    Best,
+}
+
+impl Quality {
+    pub const ALL: [Quality; 4] = [Quality::Low, Quality::Medium, Quality::High, Quality::Best];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Quality::Low => "low",
+            Quality::Medium => "medium",
+            Quality::High => "high",
+            Quality::Best => "best",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Quality> {
+        Self::ALL.into_iter().find(|q| q.name() == name)
+    }
+
+    fn to_ruffle(self) -> StageQuality {
+        match self {
+            Quality::Low => StageQuality::Low,
+            Quality::Medium => StageQuality::Medium,
+            Quality::High => StageQuality::High,
+            Quality::Best => StageQuality::Best,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ScaleMode {
+    /// fit the whole stage in the player, keeping its aspect ratio
+    ShowAll,
+    /// fill the player, keeping the aspect ratio and cropping what doesn't fit
+    NoBorder,
+    /// stretch to fill the player
+    ExactFit,
+    /// draw at 100% no matter how big the player is
+    NoScale,
+}
+
+impl ScaleMode {
+    pub const ALL: [ScaleMode; 4] = [ScaleMode::ShowAll, ScaleMode::NoBorder, ScaleMode::ExactFit, ScaleMode::NoScale];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ScaleMode::ShowAll => "show all",
+            ScaleMode::NoBorder => "no border",
+            ScaleMode::ExactFit => "stretch",
+            ScaleMode::NoScale => "no scale",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ScaleMode> {
+        Self::ALL.into_iter().find(|m| m.name() == name)
+    }
+
+    fn to_ruffle(self) -> StageScaleMode {
+        match self {
+            ScaleMode::ShowAll => StageScaleMode::ShowAll,
+            ScaleMode::NoBorder => StageScaleMode::NoBorder,
+            ScaleMode::ExactFit => StageScaleMode::ExactFit,
+            ScaleMode::NoScale => StageScaleMode::NoScale,
+        }
+    }
+}
+
+/// How the user last set up the player for a post.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct FlashPrefs {
+    /// 0 to 1
+    pub volume: f32,
+    pub muted: bool,
+    pub quality: Quality,
+    pub scale_mode: ScaleMode,
+    /// black bars around the stage instead of showing whatever's off the edge of it
+    pub letterbox: bool,
+}
+
+impl Default for FlashPrefs {
+    fn default() -> Self {
+        Self {
+            volume: 1.0,
+            muted: false,
+            quality: Quality::High,
+            scale_mode: ScaleMode::ShowAll,
+            letterbox: true,
+        }
+    }
+}
+
+impl FlashPrefs {
+    /// Pushes these preferences into a running player.
+    pub fn apply(&self, player: &EguiRufflePlayer) {
+        let mut player = player.player().lock().unwrap();
+        player.set_volume(if self.muted { 0.0 } else { self.volume });
+        player.set_quality(self.quality.to_ruffle());
+        player.set_letterbox(if self.letterbox { Letterbox::On } else { Letterbox::Off });
+        let scale_mode = self.scale_mode.to_ruffle();
+        player.mutate_with_update_context(|context| {
+            let stage = context.stage;
+            stage.set_scale_mode(context, scale_mode);
+        });
+    }
+}
+
+/// Preferences for every post the user has changed them on.  Stored as a CSV with one row per
+/// post, in the same spirit as `TagUsage`.
+pub struct FlashPrefsStore {
+    prefs: HashMap<u32, FlashPrefs>,
+    path: PathBuf,
+    /// whether `prefs` has changed since it was last written out
+    dirty: bool,
+}
+
+type PrefsRow = (u32, f32, bool, String, String, bool);
+
+impl FlashPrefsStore {
+    /// Loads preferences from `path`.  A missing or unreadable file just means defaults everywhere.
+    pub fn load(path: PathBuf) -> Self {
+        let mut prefs = HashMap::new();
+        match csv::ReaderBuilder::new().has_headers(false).from_path(&path) {
+            Ok(mut reader) => {
+                for record in reader.deserialize::<PrefsRow>() {
+                    let parsed = record.map_err(|e| e.to_string()).and_then(|(id, volume, muted, quality, scale_mode, letterbox)| {
+                        let quality = Quality::from_name(&quality).ok_or_else(|| format!("unknown quality {}", quality))?;
+                        let scale_mode = ScaleMode::from_name(&scale_mode).ok_or_else(|| format!("unknown scale mode {}", scale_mode))?;
+                        Ok((id, FlashPrefs { volume, muted, quality, scale_mode, letterbox }))
+                    });
+                    match parsed {
+                        Ok((id, p)) => { prefs.insert(id, p); },
+                        Err(e) => tracing::warn!("Skipping bad line in {}: {}", path.display(), e),
+                    }
+                }
+            },
+            Err(e) => tracing::info!("No Flash preferences loaded from {}: {}", path.display(), e),
+        }
+        Self { prefs, path, dirty: false }
+    }
+
+    pub fn get(&self, post_id: u32) -> FlashPrefs {
+        self.prefs.get(&post_id).copied().unwrap_or_default()
+    }
+
+    /// Remembers `prefs` for `post_id`.  Nothing is written out until `flush()`, so dragging the
+    /// volume slider doesn't rewrite the file every frame.
+    pub fn set(&mut self, post_id: u32, prefs: FlashPrefs) {
+        if prefs == FlashPrefs::default() {
+            self.prefs.remove(&post_id);
+        } else {
+            self.prefs.insert(post_id, prefs);
+        }
+        self.dirty = true;
+    }
+
+    /// Writes everything back out, if anything changed since last time.
+    pub fn flush(&mut self) {
+        if !self.dirty {
+            return;
+        }
+        self.dirty = false;
+        if let Err(e) = self.save() {
+            tracing::warn!("Unable to save Flash preferences to {}: {}", self.path.display(), e);
+        }
+    }
+
+    fn save(&self) -> Result<(), csv::Error> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut writer = csv::WriterBuilder::new().has_headers(false).from_path(&self.path)?;
+        for (id, p) in self.prefs.iter() {
+            writer.serialize((id, p.volume, p.muted, p.quality.name(), p.scale_mode.name(), p.letterbox))?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// What the controls want done that they can't do to the player themselves.
+pub enum ControlAction {
+    /// throw the player away and start the movie over
+    Restart,
+}
+
+fn with_player<R>(player: &EguiRufflePlayer, f: impl FnOnce(&mut Player) -> R) -> R {
+    f(&mut player.player().lock().unwrap())
+}
+
+fn toggle_fullscreen(ctx: &Context) {
+    let is_fullscreen = ctx.input(|i| i.viewport().fullscreen.unwrap_or(false));
+    ctx.send_viewport_cmd(ViewportCommand::Fullscreen(!is_fullscreen));
+}
+
+/// The controls themselves, shared by the bar and the context menu.  Changes to `prefs` are
+/// applied to the player right away; the caller only has to remember them.
+fn show_controls(ui: &mut Ui, player: &EguiRufflePlayer, prefs: &mut FlashPrefs) -> Option<ControlAction> {
+    let mut action = None;
+    let old_prefs = *prefs;
+    let is_playing = with_player(player, |p| p.is_playing());
+    if ui.button(if is_playing { "⏸ Pause" } else { "▶ Play" }).clicked() {
+        with_player(player, |p| p.set_is_playing(!is_playing));
+    }
+    if ui.button("⟲ Restart").clicked() {
+        action = Some(ControlAction::Restart);
+    }
+    ui.toggle_value(&mut prefs.muted, if prefs.muted { "🔇" } else { "🔊" }).on_hover_text("Mute");
+    ui.add_enabled(!prefs.muted, Slider::new(&mut prefs.volume, 0.0..=1.0).show_value(false)).on_hover_text("Volume");
+    egui::ComboBox::from_id_source("flash_quality").selected_text(format!("Quality: {}", prefs.quality.name())).show_ui(ui, |ui| {
+        for quality in Quality::ALL {
+            ui.selectable_value(&mut prefs.quality, quality, quality.name());
+        }
+    });
+    egui::ComboBox::from_id_source("flash_scale_mode").selected_text(format!("Scale: {}", prefs.scale_mode.name())).show_ui(ui, |ui| {
+        for mode in ScaleMode::ALL {
+            ui.selectable_value(&mut prefs.scale_mode, mode, mode.name());
+        }
+    });
+    ui.checkbox(&mut prefs.letterbox, "Letterbox");
+    if ui.button("⛶ Fullscreen").clicked() {
+        toggle_fullscreen(ui.ctx());
+    }
+    if *prefs != old_prefs {
+        prefs.apply(player);
+    }
+    action
+}
+
+/// The bar shown above the player.
+pub fn show_control_bar(ui: &mut Ui, player: &EguiRufflePlayer, prefs: &mut FlashPrefs) -> Option<ControlAction> {
+    ui.horizontal(|ui| show_controls(ui, player, prefs)).inner
+}
+
+/// The same controls, for right-clicking on the player.
+pub fn show_context_menu(ui: &mut Ui, player: &EguiRufflePlayer, prefs: &mut FlashPrefs) -> Option<ControlAction> {
+    let action = show_controls(ui, player, prefs);
+    if action.is_some() {
+        ui.close_menu();
+    }
+    action
+}
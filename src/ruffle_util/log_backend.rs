@@ -0,0 +1,107 @@
+use egui::{Color32, RichText, ScrollArea, Ui};
+use ruffle_core::backend::log::LogBackend;
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// How many lines the console keeps before dropping the oldest.
+const MAX_LINES: usize = 2000;
+
+#[derive(Clone, Copy)]
+enum LineKind {
+    Trace,
+    Warning,
+}
+
+/// Everything the current movie has traced or been warned about.
+#[derive(Default)]
+pub struct FlashConsole {
+    /// the post whose movie is logging here
+    post_id: Option<u32>,
+    lines: VecDeque<(LineKind, String)>,
+    status: Option<String>,
+}
+
+impl FlashConsole {
+    /// Clears out the last movie's output, for a new movie from post `post_id`.
+    pub fn start(&mut self, post_id: u32) {
+        self.post_id = Some(post_id);
+        self.lines.clear();
+        self.status = None;
+    }
+
+    fn push(&mut self, kind: LineKind, message: &str) {
+        if self.lines.len() >= MAX_LINES {
+            self.lines.pop_front();
+        }
+        self.lines.push_back((kind, message.to_string()));
+    }
+
+    fn text(&self) -> String {
+        self.lines.iter().map(|(kind, line)| match kind {
+            LineKind::Trace => format!("{}\n", line),
+            LineKind::Warning => format!("warning: {}\n", line),
+        }).collect()
+    }
+
+    /// Draws the console.  Exports go to `export_dir`.
+    pub fn show(&mut self, ui: &mut Ui, export_dir: &Path) {
+        ui.horizontal(|ui| {
+            if ui.button("Copy").clicked() {
+                let text = self.text();
+                ui.output_mut(|o| o.copied_text = text);
+            }
+            if ui.button("Export").clicked() {
+                let path = export_dir.join(format!("flash-console-{}.txt", self.post_id.unwrap_or(0)));
+                let result = std::fs::create_dir_all(export_dir).and_then(|()| std::fs::write(&path, self.text()));
+                self.status = Some(match result {
+                    Ok(()) => format!("Saved to {}", path.display()),
+                    Err(e) => format!("Error saving {}: {}", path.display(), e),
+                });
+            }
+            if ui.button("Clear").clicked() {
+                self.lines.clear();
+            }
+            if let Some(ref status) = self.status {
+                ui.label(status);
+            }
+        });
+        ui.separator();
+        ScrollArea::vertical().stick_to_bottom(true).auto_shrink([false, false]).show(ui, |ui| {
+            if self.lines.is_empty() {
+                ui.weak("Nothing logged yet.");
+            }
+            for (kind, line) in self.lines.iter() {
+                let text = RichText::new(line).monospace();
+                ui.label(match kind {
+                    LineKind::Trace => text,
+                    LineKind::Warning => text.color(Color32::YELLOW),
+                });
+            }
+        });
+    }
+}
+
+/// Sends a movie's `trace()` output and Ruffle's warnings about it to the console, and to
+/// `tracing` too.
+pub struct ConsoleLogBackend {
+    console: Arc<Mutex<FlashConsole>>,
+}
+
+impl ConsoleLogBackend {
+    pub fn new(console: Arc<Mutex<FlashConsole>>) -> Self {
+        ConsoleLogBackend { console }
+    }
+}
+
+impl LogBackend for ConsoleLogBackend {
+    fn avm_trace(&self, message: &str) {
+        tracing::info!(target: "avm_trace", "{}", message);
+        self.console.lock().unwrap().push(LineKind::Trace, message);
+    }
+
+    fn avm_warning(&self, message: &str) {
+        tracing::warn!(target: "avm_warning", "{}", message);
+        self.console.lock().unwrap().push(LineKind::Warning, message);
+    }
+}
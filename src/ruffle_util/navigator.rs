@@ -0,0 +1,296 @@
+//! A `NavigatorBackend` that keeps movies on a short leash: they can load things from the server
+//! their own file came from, and nothing else.  Pages they want to open go through the same
+//! confirmation dialog as any other URL.
+
+use egui::Context;
+use indexmap::IndexMap;
+use ruffle_core::backend::navigator::{ErrorResponse, NavigationMethod, NavigatorBackend, OwnedFuture, Request, SuccessResponse};
+use ruffle_core::loader::Error;
+use ruffle_core::socket::{ConnectionState, SocketAction, SocketHandle};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::rc::{Rc, Weak};
+use std::sync::{atomic::{AtomicU64, Ordering}, Arc, Mutex};
+use std::task::{Poll, Wake, Waker};
+use std::time::Duration;
+use url::{ParseError, Url};
+
+use super::ui_backend::{FlashDialog, FlashUiState};
+
+struct RepaintWaker(Context);
+
+impl Wake for RepaintWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.request_repaint();
+    }
+}
+
+/// Runs the futures Ruffle spawns (mostly loads) on the UI thread.  Waking any of them just
+/// requests a repaint, and `poll_all` gets called every frame.
+///
+/// Each task remembers the navigator that spawned it, and is dropped once that navigator is --
+/// i.e. once its player is thrown away on restart or on moving to another post -- so an old
+/// movie's loads don't keep running after it's gone.
+#[derive(Clone)]
+pub struct FlashExecutor {
+    tasks: Rc<RefCell<Vec<(Weak<()>, OwnedFuture<(), Error>)>>>,
+    waker: Waker,
+}
+
+impl FlashExecutor {
+    pub fn new(ctx: Context) -> Self {
+        FlashExecutor {
+            tasks: Rc::new(RefCell::new(Vec::new())),
+            waker: Waker::from(Arc::new(RepaintWaker(ctx))),
+        }
+    }
+
+    fn spawn(&self, owner: Weak<()>, future: OwnedFuture<(), Error>) {
+        self.tasks.borrow_mut().push((owner, future));
+        self.waker.wake_by_ref();
+    }
+
+    pub fn poll_all(&self) {
+        // take the tasks out first, since polling one may spawn more
+        let tasks = std::mem::take(&mut *self.tasks.borrow_mut());
+        let mut cx = std::task::Context::from_waker(&self.waker);
+        let mut pending = Vec::new();
+        for (owner, mut task) in tasks {
+            if owner.strong_count() == 0 {
+                continue;
+            }
+            match task.as_mut().poll(&mut cx) {
+                Poll::Pending => pending.push((owner, task)),
+                Poll::Ready(Ok(())) => {},
+                Poll::Ready(Err(e)) => tracing::warn!("Flash loader error: {}", e),
+            }
+        }
+        let mut tasks = self.tasks.borrow_mut();
+        pending.append(&mut tasks);
+        *tasks = pending;
+    }
+}
+
+/// Hands the result of an `ehttp` request, which arrives on another thread, back to a future.
+struct PendingFetch {
+    state: Arc<Mutex<(Option<ehttp::Result<ehttp::Response>>, Option<Waker>)>>,
+}
+
+impl Future for PendingFetch {
+    type Output = ehttp::Result<ehttp::Response>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.state.lock().unwrap();
+        match state.0.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                state.1 = Some(cx.waker().clone());
+                Poll::Pending
+            },
+        }
+    }
+}
+
+/// Saves a successful response to `cache_path`, or swaps a failed request for whatever was saved
+/// there last time.  Offline, a stale copy is better than nothing.
+fn through_cache(url: &str, result: ehttp::Result<ehttp::Response>, cache_path: &Path) -> ehttp::Result<ehttp::Response> {
+    match result {
+        Ok(response) => {
+            if response.ok {
+                // written to a temp file first, so an interrupted write can't leave a truncated
+                // asset to be served offline later.  the counter keeps two loads of the same URL
+                // from sharing one.
+                static TMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+                let tmp_path = cache_path.with_extension(format!("{}.tmp", TMP_COUNTER.fetch_add(1, Ordering::Relaxed)));
+                let _ = std::fs::create_dir_all(cache_path.parent().unwrap_or(cache_path));
+                let written = std::fs::write(&tmp_path, &response.bytes).and_then(|()| std::fs::rename(&tmp_path, cache_path));
+                if let Err(e) = written {
+                    tracing::warn!("Unable to cache {}: {}", url, e);
+                    let _ = std::fs::remove_file(&tmp_path);
+                }
+            }
+            Ok(response)
+        },
+        Err(e) => match std::fs::read(cache_path) {
+            Ok(bytes) => {
+                tracing::info!("Loading {} from cache: {}", url, e);
+                Ok(ehttp::Response { url: url.to_owned(), ok: true, status: 200, status_text: "OK".into(), headers: ehttp::Headers::default(), bytes })
+            },
+            Err(_) => Err(e),
+        },
+    }
+}
+
+/// Starts `request`, going through the cache at `cache_path` if there is one.  The cache is read
+/// and written from ehttp's thread, so big assets don't hold up the UI.
+fn fetch_async(request: ehttp::Request, cache_path: Option<PathBuf>) -> PendingFetch {
+    let state = Arc::new(Mutex::new((None, None)));
+    let callback_state = state.clone();
+    let url = request.url.clone();
+    ehttp::fetch(request, move |result| {
+        let result = match cache_path {
+            Some(ref cache_path) => through_cache(&url, result, cache_path),
+            None => result,
+        };
+        let mut state = callback_state.lock().unwrap();
+        state.0 = Some(result);
+        if let Some(waker) = state.1.take() {
+            waker.wake();
+        }
+    });
+    PendingFetch { state }
+}
+
+struct FetchedResponse {
+    url: String,
+    status: u16,
+    redirected: bool,
+    body: Option<Vec<u8>>,
+}
+
+impl SuccessResponse for FetchedResponse {
+    fn url(&self) -> Cow<str> {
+        Cow::Borrowed(&self.url)
+    }
+
+    fn body(self: Box<Self>) -> OwnedFuture<Vec<u8>, Error> {
+        Box::pin(async move { Ok(self.body.unwrap_or_default()) })
+    }
+
+    fn text_encoding(&self) -> Option<&'static encoding_rs::Encoding> {
+        None
+    }
+
+    fn status(&self) -> u16 {
+        self.status
+    }
+
+    fn redirected(&self) -> bool {
+        self.redirected
+    }
+
+    fn next_chunk(&mut self) -> OwnedFuture<Option<Vec<u8>>, Error> {
+        // the whole body is already here, so it all comes out as one chunk
+        let chunk = self.body.take();
+        Box::pin(async move { Ok(chunk) })
+    }
+
+    fn expected_length(&self) -> Result<Option<u64>, Error> {
+        Ok(self.body.as_ref().map(|b| b.len() as u64))
+    }
+}
+
+fn error_response(url: String, message: String) -> OwnedFuture<Box<dyn SuccessResponse>, ErrorResponse> {
+    Box::pin(async move { Err(ErrorResponse { url, error: Error::FetchError(message) }) })
+}
+
+/// 64-bit FNV-1a, for naming cache files.  Unlike `DefaultHasher` it won't change between Rust
+/// versions and orphan the whole cache.
+fn fnv1a(s: &str) -> u64 {
+    s.bytes().fold(0xcbf29ce484222325, |hash, b| (hash ^ b as u64).wrapping_mul(0x100000001b3))
+}
+
+pub struct SandboxedNavigator {
+    /// the movie's own URL, which relative URLs are resolved against and which decides what
+    /// server it's allowed to talk to
+    base_url: Url,
+    executor: FlashExecutor,
+    /// only here to be dropped along with the navigator, which tells `executor` to drop this
+    /// navigator's tasks
+    alive: Rc<()>,
+    ui: Arc<Mutex<FlashUiState>>,
+    ctx: Context,
+    /// where successfully loaded assets are kept, for playing offline later
+    cache_dir: PathBuf,
+}
+
+impl SandboxedNavigator {
+    pub fn new(base_url: Url, executor: FlashExecutor, ctx: Context, ui: Arc<Mutex<FlashUiState>>, cache_dir: PathBuf) -> Self {
+        SandboxedNavigator { base_url, executor, alive: Rc::new(()), ui, ctx, cache_dir }
+    }
+
+    fn is_allowed(&self, url: &Url) -> bool {
+        url.scheme() == self.base_url.scheme() && url.host_str() == self.base_url.host_str() && url.port_or_known_default() == self.base_url.port_or_known_default()
+    }
+
+    fn cache_path(&self, url: &Url) -> PathBuf {
+        self.cache_dir.join(format!("{:016x}", fnv1a(url.as_str())))
+    }
+}
+
+impl NavigatorBackend for SandboxedNavigator {
+    fn navigate_to_url(&self, url: &str, _target: &str, vars_method: Option<(NavigationMethod, IndexMap<String, String>)>) {
+        if url.trim_start().to_ascii_lowercase().starts_with("javascript:") {
+            tracing::info!("Ignoring javascript: URL from movie");
+            return;
+        }
+        let mut url = match self.resolve_url(url) {
+            Ok(url) => url,
+            Err(e) => {
+                tracing::warn!("Movie tried to open invalid URL \"{}\": {}", url, e);
+                return;
+            }
+        };
+        // the browser can only be asked to GET, so POSTed variables go in the query string too
+        if let Some((_, vars)) = vars_method {
+            url.query_pairs_mut().extend_pairs(vars.iter());
+        }
+        self.ui.lock().unwrap().dialogs.push(FlashDialog::OpenUrl(url.to_string()));
+        self.ctx.request_repaint();
+    }
+
+    fn fetch(&self, request: Request) -> OwnedFuture<Box<dyn SuccessResponse>, ErrorResponse> {
+        let url = match self.resolve_url(request.url()) {
+            Ok(url) => url,
+            Err(e) => return error_response(request.url().to_string(), e.to_string()),
+        };
+        if !self.is_allowed(&url) {
+            tracing::warn!("Blocked movie from loading {}", url);
+            return error_response(url.to_string(), "blocked: movies may only load from their own server".into());
+        }
+        let is_get = request.method() == NavigationMethod::Get;
+        let http_request = match request.body() {
+            Some((data, mime)) if !is_get => {
+                let mut r = ehttp::Request::post(url.as_str(), data.clone());
+                r.headers.insert("Content-Type", mime);
+                r
+            },
+            _ => ehttp::Request::get(url.as_str()),
+        };
+        let pending = fetch_async(http_request, is_get.then(|| self.cache_path(&url)));
+        Box::pin(async move {
+            match pending.await {
+                Ok(response) if response.ok => {
+                    Ok(Box::new(FetchedResponse {
+                        redirected: response.url != url.as_str(),
+                        url: response.url,
+                        status: response.status,
+                        body: Some(response.bytes),
+                    }) as Box<dyn SuccessResponse>)
+                },
+                Ok(response) => Err(ErrorResponse { url: url.to_string(), error: Error::FetchError(format!("HTTP {} {}", response.status, response.status_text)) }),
+                Err(e) => Err(ErrorResponse { url: url.to_string(), error: Error::FetchError(e) }),
+            }
+        })
+    }
+
+    fn resolve_url(&self, url: &str) -> Result<Url, ParseError> {
+        self.base_url.join(url)
+    }
+
+    fn spawn_future(&mut self, future: OwnedFuture<(), Error>) {
+        self.executor.spawn(Rc::downgrade(&self.alive), future);
+    }
+
+    fn pre_process_url(&self, url: Url) -> Url {
+        url
+    }
+
+    fn connect_socket(&mut self, host: String, port: u16, _timeout: Duration, handle: SocketHandle, _receiver: async_channel::Receiver<Vec<u8>>, sender: async_channel::Sender<SocketAction>) {
+        tracing::warn!("Blocked movie from opening a socket to {}:{}", host, port);
+        let _ = sender.try_send(SocketAction::Connect(handle, ConnectionState::Failed));
+    }
+}
@@ -0,0 +1,248 @@
+use ruffle_core::backend::storage::StorageBackend;
+use sqlite::{Connection, State};
+use std::path::Path;
+
+use super::storage::{DiskStorageBackend, DEFAULT_GLOBAL_QUOTA, DEFAULT_MOVIE_QUOTA};
+
+const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS shared_objects (
+    post_id INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    data BLOB NOT NULL,
+    PRIMARY KEY (post_id, name)
+)";
+
+/// Keeps every movie's shared objects in one SQLite database, keyed by post id and object name,
+/// instead of a file per object like `DiskStorageBackend`.
+pub struct SqliteStorageBackend {
+    conn: Connection,
+    post_id: u32,
+    movie_quota: u64,
+    global_quota: u64,
+}
+
+impl SqliteStorageBackend {
+    /// Opens (creating if needed) the database at `db_path`, as storage for the movie of post
+    /// `post_id`.
+    pub fn open(db_path: &Path, post_id: u32) -> sqlite::Result<Self> {
+        if let Some(parent) = db_path.parent() {
+            if let Err(e) = std::fs::create_dir_all(parent) {
+                tracing::warn!("Unable to create {}: {}", parent.display(), e);
+            }
+        }
+        let mut conn = sqlite::open(db_path)?;
+        // other players and the migration tool may have the database open at the same time
+        conn.set_busy_timeout(5000)?;
+        conn.execute(SCHEMA)?;
+        Ok(SqliteStorageBackend {
+            conn,
+            post_id,
+            movie_quota: DEFAULT_MOVIE_QUOTA,
+            global_quota: DEFAULT_GLOBAL_QUOTA,
+        })
+    }
+
+    /// Names of every shared object this movie has saved, as they'd be passed to `get`.
+    pub fn shared_object_names(&self) -> sqlite::Result<Vec<String>> {
+        let mut stmt = self.conn.prepare("SELECT name FROM shared_objects WHERE post_id = ? ORDER BY name")?;
+        stmt.bind((1, self.post_id as i64))?;
+        let mut names = Vec::new();
+        while stmt.next()? == State::Row {
+            names.push(stmt.read::<String, _>(0)?);
+        }
+        Ok(names)
+    }
+
+    fn read(&self, name: &str) -> sqlite::Result<Option<Vec<u8>>> {
+        let mut stmt = self.conn.prepare("SELECT data FROM shared_objects WHERE post_id = ? AND name = ?")?;
+        stmt.bind((1, self.post_id as i64))?;
+        stmt.bind((2, name))?;
+        match stmt.next()? {
+            State::Row => Ok(Some(stmt.read::<Vec<u8>, _>(0)?)),
+            State::Done => Ok(None),
+        }
+    }
+
+    fn size(&self, name: &str) -> sqlite::Result<Option<u64>> {
+        let mut stmt = self.conn.prepare("SELECT length(data) FROM shared_objects WHERE post_id = ? AND name = ?")?;
+        stmt.bind((1, self.post_id as i64))?;
+        stmt.bind((2, name))?;
+        match stmt.next()? {
+            State::Row => Ok(Some(stmt.read::<i64, _>(0)? as u64)),
+            State::Done => Ok(None),
+        }
+    }
+
+    /// Bytes stored by this movie, or by every movie if `all_movies` is set.
+    fn usage(&self, all_movies: bool) -> sqlite::Result<u64> {
+        let mut stmt = if all_movies {
+            self.conn.prepare("SELECT COALESCE(SUM(length(data)), 0) FROM shared_objects")?
+        } else {
+            let mut stmt = self.conn.prepare("SELECT COALESCE(SUM(length(data)), 0) FROM shared_objects WHERE post_id = ?")?;
+            stmt.bind((1, self.post_id as i64))?;
+            stmt
+        };
+        stmt.next()?;
+        Ok(stmt.read::<i64, _>(0)? as u64)
+    }
+
+    /// The body of `put`, run inside a transaction so the quota check and the write can't be
+    /// interleaved with another player's.  Returns false if the write would go over quota.
+    fn write(&self, name: &str, value: &[u8]) -> sqlite::Result<bool> {
+        let old_size = self.size(name)?.unwrap_or(0);
+        let movie_usage = self.usage(false)?.saturating_sub(old_size) + value.len() as u64;
+        if movie_usage > self.movie_quota {
+            tracing::warn!("Not saving \"{}\": movie would use {} bytes, over its quota of {}", name, movie_usage, self.movie_quota);
+            return Ok(false);
+        }
+        let global_usage = self.usage(true)?.saturating_sub(old_size) + value.len() as u64;
+        if global_usage > self.global_quota {
+            tracing::warn!("Not saving \"{}\": Flash saves would use {} bytes, over the global quota of {}", name, global_usage, self.global_quota);
+            return Ok(false);
+        }
+        let mut stmt = self.conn.prepare("INSERT OR REPLACE INTO shared_objects (post_id, name, data) VALUES (?, ?, ?)")?;
+        stmt.bind((1, self.post_id as i64))?;
+        stmt.bind((2, name))?;
+        stmt.bind((3, value))?;
+        stmt.next()?;
+        Ok(true)
+    }
+}
+
+impl StorageBackend for SqliteStorageBackend {
+    fn get(&self, name: &str) -> Option<Vec<u8>> {
+        match self.read(name) {
+            Ok(data) => data,
+            Err(e) => {
+                tracing::warn!("Unable to read shared object \"{}\": {}", name, e);
+                None
+            }
+        }
+    }
+
+    fn get_size(&self, name: &str) -> Option<usize> {
+        match self.size(name) {
+            Ok(size) => size.and_then(|s| s.try_into().ok()),
+            Err(e) => {
+                tracing::warn!("Unable to fetch size of shared object \"{}\": {}", name, e);
+                None
+            }
+        }
+    }
+
+    fn put(&mut self, name: &str, value: &[u8]) -> bool {
+        // IMMEDIATE takes the write lock up front, so nobody else can write between the quota
+        // check and the insert.
+        if let Err(e) = self.conn.execute("BEGIN IMMEDIATE") {
+            tracing::warn!("Unable to save \"{}\": {}", name, e);
+            return false;
+        }
+        let result = self.write(name, value).and_then(|written| {
+            if written {
+                self.conn.execute("COMMIT")?;
+            }
+            Ok(written)
+        });
+        match result {
+            Ok(true) => true,
+            Ok(false) => {
+                let _ = self.conn.execute("ROLLBACK");
+                false
+            },
+            Err(e) => {
+                tracing::warn!("Unable to save \"{}\": {}", name, e);
+                let _ = self.conn.execute("ROLLBACK");
+                false
+            },
+        }
+    }
+
+    fn remove_key(&mut self, name: &str) {
+        let result = self.conn.prepare("DELETE FROM shared_objects WHERE post_id = ? AND name = ?").and_then(|mut stmt| {
+            stmt.bind((1, self.post_id as i64))?;
+            stmt.bind((2, name))?;
+            stmt.next()
+        });
+        if let Err(e) = result {
+            tracing::warn!("Unable to remove shared object \"{}\": {}", name, e);
+        }
+    }
+}
+
+/// Every post with saves in the database at `db_path`, along with how many bytes it's using.
+pub fn list_posts(db_path: &Path) -> sqlite::Result<Vec<(u32, u64)>> {
+    let mut conn = sqlite::open(db_path)?;
+    conn.set_busy_timeout(5000)?;
+    conn.execute(SCHEMA)?;
+    let mut stmt = conn.prepare("SELECT post_id, SUM(length(data)) FROM shared_objects GROUP BY post_id")?;
+    let mut posts = Vec::new();
+    while stmt.next()? == State::Row {
+        posts.push((stmt.read::<i64, _>(0)? as u32, stmt.read::<i64, _>(1)? as u64));
+    }
+    Ok(posts)
+}
+
+/// Removes every shared object of post `post_id` from the database at `db_path`.
+pub fn delete_post(db_path: &Path, post_id: u32) -> sqlite::Result<()> {
+    let mut conn = sqlite::open(db_path)?;
+    conn.set_busy_timeout(5000)?;
+    let mut stmt = conn.prepare("DELETE FROM shared_objects WHERE post_id = ?")?;
+    stmt.bind((1, post_id as i64))?;
+    stmt.next()?;
+    Ok(())
+}
+
+/// Copies every save under `saves_dir` (one directory per post id, as `DiskStorageBackend` lays
+/// them out) into the database at `db_path`, replacing anything already there with the same name.
+/// The files are left alone.  Returns how many shared objects were copied.
+pub fn migrate_from_disk(saves_dir: &Path, db_path: &Path) -> Result<usize, String> {
+    let entries = std::fs::read_dir(saves_dir).map_err(|e| format!("Error reading {}: {}", saves_dir.display(), e))?;
+    let mut db = SqliteStorageBackend::open(db_path, 0).map_err(|e| format!("Error opening {}: {}", db_path.display(), e))?;
+    // all or nothing, so a failure partway doesn't leave half the saves copied
+    db.conn.execute("BEGIN IMMEDIATE").map_err(|e| format!("Error writing {}: {}", db_path.display(), e))?;
+    let copy = || -> Result<usize, String> {
+        let mut count = 0;
+        for entry in entries.flatten() {
+            let Some(post_id) = entry.file_name().to_str().and_then(|s| s.parse::<u32>().ok()) else { continue };
+            let disk = DiskStorageBackend::new(entry.path());
+            db.post_id = post_id;
+            for name in disk.shared_object_names() {
+                let Some(data) = disk.get(&name) else { continue };
+                match db.write(&name, &data) {
+                    Ok(true) => count += 1,
+                    Ok(false) => return Err(format!("Error copying \"{}\" of post {}: over quota", name, post_id)),
+                    Err(e) => return Err(format!("Error copying \"{}\" of post {}: {}", name, post_id, e)),
+                }
+            }
+        }
+        Ok(count)
+    };
+    let result = copy().and_then(|count| {
+        db.conn.execute("COMMIT").map_err(|e| format!("Error writing {}: {}", db_path.display(), e))?;
+        Ok(count)
+    });
+    if result.is_err() {
+        let _ = db.conn.execute("ROLLBACK");
+    }
+    result
+}
+
+/// The reverse of `migrate_from_disk`: writes every shared object in the database at `db_path`
+/// out to files under `saves_dir`.  The database is left alone.
+pub fn migrate_to_disk(db_path: &Path, saves_dir: &Path) -> Result<usize, String> {
+    let map_err = |e: sqlite::Error| format!("Error reading {}: {}", db_path.display(), e);
+    let mut conn = sqlite::open(db_path).map_err(map_err)?;
+    conn.set_busy_timeout(5000).map_err(map_err)?;
+    let mut stmt = conn.prepare("SELECT post_id, name, data FROM shared_objects ORDER BY post_id").map_err(map_err)?;
+    let mut count = 0;
+    while stmt.next().map_err(map_err)? == State::Row {
+        let post_id = stmt.read::<i64, _>(0).map_err(map_err)?;
+        let name = stmt.read::<String, _>(1).map_err(map_err)?;
+        let data = stmt.read::<Vec<u8>, _>(2).map_err(map_err)?;
+        let mut disk = DiskStorageBackend::new(saves_dir.join(post_id.to_string()));
+        if !disk.put(&name, &data) {
+            return Err(format!("Error copying \"{}\" of post {}", name, post_id));
+        }
+        count += 1;
+    }
+    Ok(count)
+}
@@ -4,31 +4,164 @@ use std::fs::File;
 use std::io::Write;
 use std::path::{Component, Path, PathBuf};
 
+/// Most a single movie may store, across all of its shared objects.
+pub const DEFAULT_MOVIE_QUOTA: u64 = 10 * 1024 * 1024;
+/// Most all movies together may store.
+pub const DEFAULT_GLOBAL_QUOTA: u64 = 500 * 1024 * 1024;
+
 pub struct DiskStorageBackend {
     shared_objects_path: PathBuf,
+    /// the directory holding every movie's storage dir, for enforcing `global_quota`
+    global_path: Option<PathBuf>,
+    movie_quota: u64,
+    global_quota: u64,
+    /// bytes used by this movie and by every movie.  Walking the directories for these is slow,
+    /// so it's only done the first time the movie saves; after that they're kept up to date as it
+    /// writes and removes things.
+    movie_usage: Option<u64>,
+    global_usage: Option<u64>,
+}
+
+/// Total size of all the files under `path`.  Missing directories count as empty.
+fn dir_size(path: &Path) -> u64 {
+    let Ok(entries) = fs::read_dir(path) else { return 0 };
+    entries.flatten().map(|entry| match entry.metadata() {
+        Ok(m) if m.is_dir() => dir_size(&entry.path()),
+        Ok(m) => m.len(),
+        Err(_) => 0,
+    }).sum()
 }
 
 impl DiskStorageBackend {
+    /// Storage rooted at `shared_objects_path`, with the default quotas.  The parent directory is
+    /// assumed to hold the storage dirs of every other movie too.
     pub fn new(shared_objects_path: PathBuf) -> Self {
-DiskStorageBackend {
+        DiskStorageBackend {
+            global_path: shared_objects_path.parent().map(Path::to_path_buf),
             shared_objects_path,
+            movie_quota: DEFAULT_MOVIE_QUOTA,
+            global_quota: DEFAULT_GLOBAL_QUOTA,
+            movie_usage: None,
+            global_usage: None,
+        }
+    }
+
+    /// Current (movie, global) usage, working it out from disk if it hasn't been yet.  Global usage
+    /// is `None` if there's no global dir to count.
+    fn usage(&mut self) -> (u64, Option<u64>) {
+        let movie = *self.movie_usage.get_or_insert_with(|| dir_size(&self.shared_objects_path));
+        if self.global_usage.is_none() {
+            self.global_usage = self.global_path.as_deref().map(dir_size);
+        }
+        (movie, self.global_usage)
+    }
+
+    /// Records that a file of `old_size` bytes now has `new_size` bytes.
+    fn update_usage(&mut self, old_size: u64, new_size: u64) {
+        for usage in [&mut self.movie_usage, &mut self.global_usage].into_iter().flatten() {
+            *usage = usage.saturating_sub(old_size) + new_size;
+        }
+    }
+
+    /// Checks whether replacing `old_size` bytes at `path` with `new_size` bytes would go over
+    /// either quota.
+    fn within_quota(&mut self, path: &Path, old_size: u64, new_size: u64) -> bool {
+        let (movie_usage, global_usage) = self.usage();
+        let movie_usage = movie_usage.saturating_sub(old_size) + new_size;
+        if movie_usage > self.movie_quota {
+            tracing::warn!("Not saving {}: movie would use {} bytes, over its quota of {}", path.display(), movie_usage, self.movie_quota);
+            return false;
+        }
+        if let Some(global_usage) = global_usage {
+            let global_usage = global_usage.saturating_sub(old_size) + new_size;
+            if global_usage > self.global_quota {
+                tracing::warn!("Not saving {}: Flash saves would use {} bytes, over the global quota of {}", path.display(), global_usage, self.global_quota);
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Turns a shared object name from the movie into a relative path, or `None` if it can't be
+    /// made safe.  Backslashes count as separators on every platform, `.` components are dropped,
+    /// and anything with `..`, a root or a drive prefix is rejected outright -- joining an
+    /// absolute path onto the storage dir would replace it entirely.
+    fn sanitize_name(name: &str) -> Option<PathBuf> {
+        let name = name.replace('\\', "/");
+        let mut path = PathBuf::new();
+        for component in Path::new(&name).components() {
+            match component {
+                Component::Normal(part) => path.push(part),
+                Component::CurDir => {},
+                Component::ParentDir | Component::RootDir | Component::Prefix(_) => return None,
+            }
         }
+        if path.as_os_str().is_empty() {
+            return None;
+        }
+        Some(path)
     }
 
-    /// Verifies that the path contains no `..` components to prevent accessing files outside of the Ruffle directory.
-    fn is_path_allowed(path: &Path) -> bool {
-        path.components().all(|c| c != Component::ParentDir)
+    /// Makes sure `path` really ends up inside the storage dir once symlinks are resolved.  Only
+    /// the parts of the path that already exist can be checked, but anything that doesn't exist
+    /// yet can't be a symlink either.
+    fn is_inside_storage_dir(&self, path: &Path) -> bool {
+        let Ok(base) = self.shared_objects_path.canonicalize() else {
+            // nothing has been saved yet, so there's nothing to escape through
+            return !self.shared_objects_path.exists();
+        };
+        let mut existing = path;
+        while !existing.exists() {
+            match existing.parent() {
+                Some(parent) => existing = parent,
+                None => return false,
+            }
+        }
+        match existing.canonicalize() {
+            Ok(resolved) => resolved.starts_with(&base),
+            Err(_) => false,
+        }
     }
 
-    fn get_shared_object_path(&self, name: &str) -> PathBuf {
-        self.shared_objects_path.join(format!("{name}.sol"))
+    /// Names of every shared object saved so far, as they'd be passed to `get`.
+    pub fn shared_object_names(&self) -> Vec<String> {
+        fn walk(dir: &Path, prefix: &str, names: &mut Vec<String>) {
+            let Ok(entries) = fs::read_dir(dir) else { return };
+            for entry in entries.flatten() {
+                let Some(file_name) = entry.file_name().to_str().map(str::to_owned) else { continue };
+                let name = if prefix.is_empty() { file_name } else { format!("{}/{}", prefix, file_name) };
+                if entry.path().is_dir() {
+                    walk(&entry.path(), &name, names);
+                } else if let Some(name) = name.strip_suffix(".sol") {
+                    names.push(name.to_owned());
+                }
+            }
+        }
+        let mut names = Vec::new();
+        walk(&self.shared_objects_path, "", &mut names);
+        names
+    }
+
+    /// Where the shared object `name` lives on disk, or `None` if the name isn't allowed.
+    fn get_shared_object_path(&self, name: &str) -> Option<PathBuf> {
+        let relative = Self::sanitize_name(name)?;
+        let mut file_name = relative.file_name()?.to_os_string();
+        file_name.push(".sol");
+        let path = self.shared_objects_path.join(relative.with_file_name(file_name));
+        if !self.is_inside_storage_dir(&path) {
+            tracing::warn!("Refusing to access shared object \"{}\" outside the storage dir", name);
+            return None;
+        }
+        Some(path)
     }
 }
 
 impl StorageBackend for DiskStorageBackend {
     fn get(&self, name: &str) -> Option<Vec<u8>> {
-        let path = self.get_shared_object_path(name);
-        if !Self::is_path_allowed(&path) {
+        let path = self.get_shared_object_path(name)?;
+        if path.is_dir() {
+            // a SharedObject name that's a prefix of others ("a" next to "a/b") -- there's no
+            // data here, just more names.
             return None;
         }
         match std::fs::read(path) {
@@ -41,11 +174,9 @@ impl StorageBackend for DiskStorageBackend {
     }
 
     fn get_size(&self, name: &str) -> Option<usize> {
-        let path = self.get_shared_object_path(name);
-        if !Self::is_path_allowed(&path) {
-            return None;
-        }
+        let path = self.get_shared_object_path(name)?;
         match std::fs::metadata(path) {
+            Ok(data) if data.is_dir() => None,
             Ok(data) => data.len().try_into().ok(),
             Err(e) => {
                 tracing::warn!("Unable to fetch metadata of file \"{}\": {:?}", name, e);
@@ -64,10 +195,9 @@ impl StorageBackend for DiskStorageBackend {
                 tracing::warn!("Unable to create storage dir {}", r);
             }
         }
-        let path = self.get_shared_object_path(name);
-        if !Self::is_path_allowed(&path) {
+        let Some(path) = self.get_shared_object_path(name) else {
             return false;
-        }
+        };
         if let Some(parent_dir) = path.parent() {
             if !parent_dir.exists() {
                 if let Err(r) = fs::create_dir_all(parent_dir) {
@@ -77,27 +207,173 @@ impl StorageBackend for DiskStorageBackend {
             }
         }
 
-        match File::create(path) {
-            Ok(mut file) => {
-                if let Err(r) = file.write_all(value) {
-                    tracing::warn!("Unable to write file content {:?}", r);
-                    false
-                } else {
-                    true
+        if path.is_dir() {
+            tracing::warn!("Unable to save {}: it's a directory", path.display());
+            return false;
+        }
+        let old_size = fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+        if !self.within_quota(&path, old_size, value.len() as u64) {
+            return false;
+        }
+
+        // write to a temp file and rename it over the real one, so a crash partway through leaves
+        // the old save intact instead of a truncated one.
+        let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+        tmp_name.push(".tmp");
+        let tmp_path = path.with_file_name(tmp_name);
+        let result = File::create(&tmp_path)
+            .and_then(|mut file| {
+                file.write_all(value)?;
+                file.sync_all()
+            })
+            .and_then(|()| fs::rename(&tmp_path, &path));
+        match result {
+            Ok(()) => {
+                // make the rename itself durable.  not possible on every platform, so don't
+                // complain if it fails.
+                if let Some(parent_dir) = path.parent() {
+                    let _ = File::open(parent_dir).and_then(|dir| dir.sync_all());
                 }
+                self.update_usage(old_size, value.len() as u64);
+                true
             }
             Err(r) => {
                 tracing::warn!("Unable to save file {:?}", r);
+                let _ = fs::remove_file(&tmp_path);
                 false
             }
         }
     }
 
     fn remove_key(&mut self, name: &str) {
-        let path = self.get_shared_object_path(name);
-        if !Self::is_path_allowed(&path) {
+        let Some(path) = self.get_shared_object_path(name) else {
             return;
+        };
+        let old_size = fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+        if fs::remove_file(path).is_ok() {
+            self.update_usage(old_size, 0);
         }
-        let _ = fs::remove_file(path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A per-post storage dir, next to a file outside it that movies shouldn't be able to reach.
+    struct Fixture {
+        _root: tempfile::TempDir,
+        storage_dir: PathBuf,
+        outside: PathBuf,
+        victim: PathBuf,
+    }
+
+    impl Fixture {
+        fn new() -> Self {
+            let root = tempfile::tempdir().unwrap();
+            let storage_dir = root.path().join("flash_saves").join("123");
+            let outside = root.path().join("outside");
+            fs::create_dir_all(&storage_dir).unwrap();
+            fs::create_dir_all(&outside).unwrap();
+            let victim = outside.join("victim.sol");
+            fs::write(&victim, b"secret").unwrap();
+            Fixture { _root: root, storage_dir, outside, victim }
+        }
+
+        fn storage(&self) -> DiskStorageBackend {
+            DiskStorageBackend::new(self.storage_dir.clone())
+        }
+
+        /// Checks that `name` can't be read, written or removed, and that the file outside the
+        /// storage dir is untouched.
+        fn assert_refused(&self, name: &str) {
+            let mut storage = self.storage();
+            assert_eq!(storage.get(name), None, "get({:?})", name);
+            assert_eq!(storage.get_size(name), None, "get_size({:?})", name);
+            assert!(!storage.put(name, b"overwritten"), "put({:?})", name);
+            storage.remove_key(name);
+            assert_eq!(fs::read(&self.victim).unwrap(), b"secret", "{:?} reached the file outside", name);
+        }
+    }
+
+    #[test]
+    fn quota_tracks_writes_and_removals() {
+        let fixture = Fixture::new();
+        let mut storage = fixture.storage();
+        storage.movie_quota = 10;
+        assert!(storage.put("a", b"123456"));
+        assert!(!storage.put("b", b"12345"));
+        // overwriting only counts the difference
+        assert!(storage.put("a", b"1234567890"));
+        storage.remove_key("a");
+        assert!(storage.put("b", b"12345"));
+        assert_eq!(storage.movie_usage, Some(5));
+    }
+
+    #[test]
+    fn absolute_name() {
+        let fixture = Fixture::new();
+        let name = fixture.outside.join("victim").to_str().unwrap().to_owned();
+        fixture.assert_refused(&name);
+    }
+
+    #[test]
+    fn backslash_separated_names() {
+        let fixture = Fixture::new();
+        fixture.assert_refused("..\\..\\outside\\victim");
+        fixture.assert_refused("sub\\..\\..\\..\\outside\\victim");
+        fixture.assert_refused("\\victim");
+
+        // harmless backslash names are fine, and end up in subdirectories
+        let mut storage = fixture.storage();
+        assert!(storage.put("a\\b", b"data"));
+        assert_eq!(fs::read(fixture.storage_dir.join("a").join("b.sol")).unwrap(), b"data");
+        assert_eq!(storage.get("a/b"), Some(b"data".to_vec()));
+    }
+
+    #[test]
+    fn parent_dir_component() {
+        let fixture = Fixture::new();
+        fixture.assert_refused("../../outside/victim");
+        fixture.assert_refused("localhost/../../../outside/victim");
+        fixture.assert_refused("..");
+    }
+
+    #[test]
+    fn rooted_names() {
+        let fixture = Fixture::new();
+        fixture.assert_refused("/victim");
+        fixture.assert_refused("//victim");
+        fixture.assert_refused("/");
+        fixture.assert_refused("");
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn drive_prefix() {
+        let fixture = Fixture::new();
+        fixture.assert_refused("C:\\victim");
+        fixture.assert_refused("C:victim");
+        fixture.assert_refused("\\\\?\\C:\\victim");
+        fixture.assert_refused("\\\\server\\share\\victim");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinked_subdirectory() {
+        let fixture = Fixture::new();
+        std::os::unix::fs::symlink(&fixture.outside, fixture.storage_dir.join("link")).unwrap();
+        fixture.assert_refused("link/victim");
+        // a name that doesn't exist yet, through the same link
+        fixture.assert_refused("link/new");
+        assert!(!fixture.outside.join("new.sol").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinked_sol_file() {
+        let fixture = Fixture::new();
+        std::os::unix::fs::symlink(&fixture.victim, fixture.storage_dir.join("victim.sol")).unwrap();
+        fixture.assert_refused("victim");
     }
 }
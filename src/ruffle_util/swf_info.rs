@@ -0,0 +1,51 @@
+use egui::Vec2;
+use ruffle_core::tag_utils::SwfMovie;
+
+/// What the SWF header says about a movie.
+#[derive(Clone, Copy, Debug)]
+pub struct SwfInfo {
+    pub version: u8,
+    /// ActionScript 3 (AVM2) rather than ActionScript 1/2 (AVM1)
+    pub avm2: bool,
+    /// stage size, in pixels
+    pub width: f64,
+    pub height: f64,
+    pub frame_rate: f64,
+    pub num_frames: u16,
+}
+
+impl SwfInfo {
+    pub fn new(movie: &SwfMovie) -> Self {
+        SwfInfo {
+            version: movie.version(),
+            avm2: movie.is_action_script_3(),
+            width: movie.width().to_pixels(),
+            height: movie.height().to_pixels(),
+            frame_rate: movie.frame_rate().to_f64(),
+            num_frames: movie.num_frames(),
+        }
+    }
+
+    /// Stage size rounded to whole pixels, never zero.
+    pub fn stage_size(&self) -> (u32, u32) {
+        ((self.width.round() as u32).max(1), (self.height.round() as u32).max(1))
+    }
+
+    /// The biggest size with the stage's aspect ratio that fits in `available`.
+    pub fn fit(&self, available: Vec2) -> Vec2 {
+        let (width, height) = self.stage_size();
+        let stage = Vec2::new(width as f32, height as f32);
+        stage * (available.x / stage.x).min(available.y / stage.y)
+    }
+}
+
+impl std::fmt::Display for SwfInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SWF {} · {} · {}×{} · {:.1} fps · {} frames",
+            self.version,
+            if self.avm2 { "AVM2" } else { "AVM1" },
+            self.width, self.height,
+            self.frame_rate,
+            self.num_frames)
+    }
+}
@@ -0,0 +1,253 @@
+use egui::{Context, CursorIcon, OpenUrl, ViewportCommand};
+use ruffle_core::backend::ui::{DialogResultFuture, FileFilter, FontDefinition, FullscreenError, LanguageIdentifier, MouseCursor, UiBackend, US_ENGLISH};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use url::Url;
+
+/// Something the movie wants to tell or ask the user, waiting to be shown.
+pub enum FlashDialog {
+    Message(String),
+    /// The movie wants to open a URL in the browser.  Never opened without the user clicking OK.
+    OpenUrl(String),
+}
+
+/// State shared between the `UiBackend` (which Ruffle owns) and the app, which applies it to egui
+/// every frame.
+pub struct FlashUiState {
+    pub cursor: CursorIcon,
+    pub mouse_visible: bool,
+    pub dialogs: Vec<FlashDialog>,
+    /// egui can only write to the system clipboard, not read it, so this is whatever was last
+    /// copied by the movie or pasted into the app.
+    clipboard: String,
+}
+
+impl Default for FlashUiState {
+    fn default() -> Self {
+        Self {
+            cursor: CursorIcon::Default,
+            mouse_visible: true,
+            dialogs: Vec::new(),
+            clipboard: String::new(),
+        }
+    }
+}
+
+impl FlashUiState {
+    /// Picks up anything pasted into the app this frame so the movie can read it back.
+    pub fn observe_input(&mut self, ctx: &Context) {
+        ctx.input(|i| {
+            for event in i.events.iter() {
+                if let egui::Event::Paste(text) = event {
+                    self.clipboard = text.clone();
+                }
+            }
+        });
+    }
+
+    /// Applies the movie's cursor while the pointer is over the player.
+    pub fn apply_cursor(&self, ctx: &Context) {
+        ctx.set_cursor_icon(if self.mouse_visible { self.cursor } else { CursorIcon::None });
+    }
+
+    /// Draws the oldest pending dialog, if there is one.
+    pub fn show_dialogs(&mut self, ctx: &Context) {
+        let Some(dialog) = self.dialogs.first() else { return };
+        let mut dismissed = false;
+        egui::Window::new("Flash").collapsible(false).resizable(false).show(ctx, |ui| {
+            match dialog {
+                FlashDialog::Message(message) => {
+                    ui.label(message);
+                    if ui.button("OK").clicked() {
+                        dismissed = true;
+                    }
+                },
+                FlashDialog::OpenUrl(url) => {
+                    ui.label("This movie wants to open a web page:");
+                    ui.monospace(url);
+                    ui.horizontal(|ui| {
+                        if ui.button("Open").clicked() {
+                            ctx.open_url(OpenUrl::new_tab(url));
+                            dismissed = true;
+                        }
+                        if ui.button("Cancel").clicked() {
+                            dismissed = true;
+                        }
+                    });
+                },
+            }
+        });
+        if dismissed {
+            self.dialogs.remove(0);
+        }
+    }
+}
+
+pub struct EguiUiBackend {
+    ctx: Context,
+    state: Arc<Mutex<FlashUiState>>,
+}
+
+impl EguiUiBackend {
+    pub fn new(ctx: Context, state: Arc<Mutex<FlashUiState>>) -> Self {
+        EguiUiBackend { ctx, state }
+    }
+
+    fn push_dialog(&self, dialog: FlashDialog) {
+        self.state.lock().unwrap().dialogs.push(dialog);
+        self.ctx.request_repaint();
+    }
+}
+
+/// Directories to look in for device fonts.
+fn font_dirs() -> Vec<PathBuf> {
+    let mut dirs = vec![
+        PathBuf::from("/usr/share/fonts"),
+        PathBuf::from("/usr/local/share/fonts"),
+        PathBuf::from("/System/Library/Fonts"),
+        PathBuf::from("/Library/Fonts"),
+        PathBuf::from("C:\\Windows\\Fonts"),
+    ];
+    if let Some(base) = directories::BaseDirs::new() {
+        dirs.push(base.data_dir().join("fonts"));
+        dirs.push(base.home_dir().join(".fonts"));
+    }
+    dirs
+}
+
+fn normalize_font_name(name: &str) -> String {
+    name.chars().filter(|c| c.is_alphanumeric()).flat_map(char::to_lowercase).collect()
+}
+
+/// Adds every font file under `dir` to `fonts`, along with its normalized name.
+fn collect_font_files(dir: &Path, fonts: &mut Vec<(String, PathBuf)>) {
+    let Ok(entries) = std::fs::read_dir(dir) else { return };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            collect_font_files(&path, fonts);
+            continue;
+        }
+        let is_font = path.extension().and_then(|e| e.to_str()).is_some_and(|e| e.eq_ignore_ascii_case("ttf") || e.eq_ignore_ascii_case("otf"));
+        if !is_font {
+            continue;
+        }
+        let Some(stem) = path.file_stem().and_then(|s| s.to_str()) else { continue };
+        fonts.push((normalize_font_name(stem), path));
+    }
+}
+
+/// Every font file on the system.  Walking the font dirs is slow, so it's only done once, the
+/// first time a movie asks for a device font.
+fn font_index() -> &'static [(String, PathBuf)] {
+    static INDEX: OnceLock<Vec<(String, PathBuf)>> = OnceLock::new();
+    INDEX.get_or_init(|| {
+        let mut fonts = Vec::new();
+        for dir in font_dirs() {
+            collect_font_files(&dir, &mut fonts);
+        }
+        tracing::info!("Found {} device fonts", fonts.len());
+        fonts
+    })
+}
+
+/// Looks for a font file whose name matches `name` and the requested style.
+fn find_font_file(name: &str, is_bold: bool, is_italic: bool) -> Option<&'static Path> {
+    for (stem, path) in font_index() {
+        let Some(style) = stem.strip_prefix(name) else { continue };
+        let bold = style.contains("bold");
+        let italic = style.contains("italic") || style.contains("oblique");
+        // anything else in the style part (light, condensed, ...) means it's not the plain face
+        let rest = style.replace("bold", "").replace("italic", "").replace("oblique", "").replace("regular", "");
+        if bold == is_bold && italic == is_italic && rest.is_empty() {
+            return Some(path.as_path());
+        }
+    }
+    None
+}
+
+impl UiBackend for EguiUiBackend {
+    fn mouse_visible(&self) -> bool {
+        self.state.lock().unwrap().mouse_visible
+    }
+
+    fn set_mouse_visible(&mut self, visible: bool) {
+        self.state.lock().unwrap().mouse_visible = visible;
+    }
+
+    fn set_mouse_cursor(&mut self, cursor: MouseCursor) {
+        self.state.lock().unwrap().cursor = match cursor {
+            MouseCursor::Arrow => CursorIcon::Default,
+            MouseCursor::Hand => CursorIcon::PointingHand,
+            MouseCursor::IBeam => CursorIcon::Text,
+            MouseCursor::Grab => CursorIcon::Grab,
+        };
+    }
+
+    fn clipboard_content(&mut self) -> String {
+        self.state.lock().unwrap().clipboard.clone()
+    }
+
+    fn set_clipboard_content(&mut self, content: String) {
+        self.ctx.output_mut(|o| o.copied_text = content.clone());
+        self.state.lock().unwrap().clipboard = content;
+    }
+
+    fn set_fullscreen(&mut self, is_full: bool) -> Result<(), FullscreenError> {
+        self.ctx.send_viewport_cmd(ViewportCommand::Fullscreen(is_full));
+        Ok(())
+    }
+
+    fn display_root_movie_download_failed_message(&self, invalid_swf: bool) {
+        let message = if invalid_swf {
+            "This post's file doesn't appear to be a valid Flash movie."
+        } else {
+            "The Flash movie could not be downloaded."
+        };
+        self.push_dialog(FlashDialog::Message(message.into()));
+    }
+
+    fn message(&self, message: &str) {
+        self.push_dialog(FlashDialog::Message(message.into()));
+    }
+
+    fn display_unsupported_video(&self, url: Url) {
+        // Ruffle can't play this video itself, but the browser might.
+        self.push_dialog(FlashDialog::OpenUrl(url.to_string()));
+    }
+
+    fn load_device_font(&self, name: &str, is_bold: bool, is_italic: bool, register: &mut dyn FnMut(FontDefinition)) {
+        let normalized = normalize_font_name(name);
+        let Some(path) = find_font_file(&normalized, is_bold, is_italic) else {
+            tracing::info!("No device font found for \"{}\" (bold: {}, italic: {})", name, is_bold, is_italic);
+            return;
+        };
+        match std::fs::read(path) {
+            Ok(data) => register(FontDefinition::FontFile {
+                name: name.to_string(),
+                is_bold,
+                is_italic,
+                data,
+                index: 0,
+            }),
+            Err(e) => tracing::warn!("Unable to read font file {}: {:?}", path.display(), e),
+        }
+    }
+
+    fn open_virtual_keyboard(&self) {}
+
+    fn language(&self) -> LanguageIdentifier {
+        US_ENGLISH.clone()
+    }
+
+    fn display_file_open_dialog(&mut self, _filters: Vec<FileFilter>) -> Option<DialogResultFuture> {
+        // no file dialog support yet; Ruffle treats None as the user cancelling.
+        None
+    }
+
+    fn display_file_save_dialog(&mut self, _file_name: String, _title: String) -> Option<DialogResultFuture> {
+        None
+    }
+
+    fn close_file_dialog(&mut self) {}
+}
@@ -0,0 +1,407 @@
+use std::{fs, io::{self, Read as _, Write as _}, path::{Path, PathBuf}, time::SystemTime};
+
+use egui::{Grid, Image, ScrollArea};
+use ruffle_core::backend::storage::StorageBackend;
+use vince621_core::db::posts::{ImageResolution, Post};
+
+use crate::{post_info::format_size, ruffle_util::{sqlite_storage::{self, SqliteStorageBackend}, storage::{DiskStorageBackend, DEFAULT_MOVIE_QUOTA}}};
+
+/// Where Flash movies' saved data goes.  `vince621 flash-saves migrate` moves it between the two.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FlashStorage {
+    /// one file per shared object under `flash_saves/<post id>/`
+    Files,
+    /// everything in `flash_saves.sqlite`
+    Sqlite,
+}
+
+impl FlashStorage {
+    pub const ALL: [FlashStorage; 2] = [FlashStorage::Files, FlashStorage::Sqlite];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            FlashStorage::Files => "files",
+            FlashStorage::Sqlite => "sqlite",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<FlashStorage> {
+        Self::ALL.into_iter().find(|s| s.name() == name)
+    }
+
+    fn setting_path(data_dir: &Path) -> PathBuf {
+        data_dir.join("flash_storage.txt")
+    }
+
+    fn saves_dir(data_dir: &Path) -> PathBuf {
+        data_dir.join("flash_saves")
+    }
+
+    fn db_path(data_dir: &Path) -> PathBuf {
+        data_dir.join("flash_saves.sqlite")
+    }
+
+    /// Loads the choice written by `save()`.  A missing file means separate files.
+    pub fn load(data_dir: &Path) -> Self {
+        let path = Self::setting_path(data_dir);
+        let Ok(name) = fs::read_to_string(&path) else { return FlashStorage::Files };
+        Self::from_name(name.trim()).unwrap_or_else(|| {
+            tracing::warn!("Unknown Flash storage {:?} in {}", name.trim(), path.display());
+            FlashStorage::Files
+        })
+    }
+
+    pub fn save(self, data_dir: &Path) {
+        let path = Self::setting_path(data_dir);
+        if let Err(e) = fs::create_dir_all(data_dir).and_then(|()| fs::write(&path, self.name())) {
+            tracing::warn!("Unable to save Flash storage setting to {}: {}", path.display(), e);
+        }
+    }
+
+    /// Storage for the movie of post `post_id`.
+    pub fn open(self, data_dir: &Path, post_id: u32) -> Result<Box<dyn StorageBackend>, String> {
+        match self {
+            FlashStorage::Files => Ok(Box::new(DiskStorageBackend::new(Self::saves_dir(data_dir).join(post_id.to_string())))),
+            FlashStorage::Sqlite => {
+                let db_path = Self::db_path(data_dir);
+                match SqliteStorageBackend::open(&db_path, post_id) {
+                    Ok(storage) => Ok(Box::new(storage)),
+                    Err(e) => Err(format!("Error opening {}: {}", db_path.display(), e)),
+                }
+            },
+        }
+    }
+}
+
+/// One post's worth of Flash saves.
+struct SaveEntry {
+    post_id: u32,
+    /// `None` for saves in the database, which doesn't keep track
+    modified: Option<SystemTime>,
+    size: u64,
+}
+
+/// Newest modification time and total size of everything under `path`.
+fn scan_dir(path: &Path) -> io::Result<(SystemTime, u64)> {
+    let mut newest = SystemTime::UNIX_EPOCH;
+    let mut size = 0;
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            let (m, s) = scan_dir(&entry.path())?;
+            newest = newest.max(m);
+            size += s;
+        } else {
+            newest = newest.max(metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH));
+            size += metadata.len();
+        }
+    }
+    Ok((newest, size))
+}
+
+fn format_age(time: SystemTime) -> String {
+    let Ok(age) = time.elapsed() else { return "just now".into() };
+    let secs = age.as_secs();
+    match secs {
+        0..=59 => "just now".into(),
+        60..=3599 => format!("{} minutes ago", secs / 60),
+        3600..=86399 => format!("{} hours ago", secs / 3600),
+        _ => format!("{} days ago", secs / 86400),
+    }
+}
+
+/// Reads each of `names` out of `storage`, skipping any that have gone missing.
+fn read_all(storage: &dyn StorageBackend, names: Vec<String>) -> Vec<(String, Vec<u8>)> {
+    names.into_iter().filter_map(|name| {
+        let data = storage.get(&name)?;
+        Some((name, data))
+    }).collect()
+}
+
+/// A shared object read out of an exported zip, waiting to be written.
+struct ImportedObject {
+    post_id: u32,
+    name: String,
+    data: Vec<u8>,
+}
+
+/// Reads every shared object out of a zip made by `SaveManager::export()`.  Every path in it has
+/// to start with a post id and end in `.sol`; anything else is skipped.  So is anything bigger
+/// than a movie could ever store, without reading more of it than that -- the size in the zip's
+/// header can't be trusted.
+fn read_import(zip_path: &Path) -> zip::result::ZipResult<Vec<ImportedObject>> {
+    let mut archive = zip::ZipArchive::new(fs::File::open(zip_path)?)?;
+    let mut objects = Vec::new();
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i)?;
+        if file.is_dir() {
+            continue;
+        }
+        let path = file.name().to_owned();
+        let Some((post_id, name)) = path.split_once('/').and_then(|(id, rest)| Some((id.parse().ok()?, rest.strip_suffix(".sol")?))) else {
+            tracing::warn!("Skipping {:?} in {}: not a shared object", path, zip_path.display());
+            continue;
+        };
+        let mut data = Vec::new();
+        (&mut file).take(DEFAULT_MOVIE_QUOTA + 1).read_to_end(&mut data)?;
+        if data.len() as u64 > DEFAULT_MOVIE_QUOTA {
+            tracing::warn!("Skipping {:?} in {}: bigger than a movie's quota", path, zip_path.display());
+            continue;
+        }
+        objects.push(ImportedObject { post_id, name: name.to_owned(), data });
+    }
+    Ok(objects)
+}
+
+/// The "Flash saves" window: lists every post with saved data in whichever storage the settings
+/// pick, and lets the user open, export, import or delete them.
+pub struct SaveManager {
+    data_dir: PathBuf,
+    storage: FlashStorage,
+    entries: Vec<SaveEntry>,
+    export_dir: String,
+    import_path: String,
+    confirm_delete: Option<u32>,
+    /// an import that would overwrite existing saves, and the posts it would overwrite
+    confirm_import: Option<(Vec<ImportedObject>, Vec<u32>)>,
+    status: Option<String>,
+}
+
+impl SaveManager {
+    pub fn new(data_dir: &Path, storage: FlashStorage) -> Self {
+        let mut this = Self {
+            data_dir: data_dir.to_path_buf(),
+            storage,
+            entries: Vec::new(),
+            export_dir: data_dir.to_string_lossy().into_owned(),
+            import_path: String::new(),
+            confirm_delete: None,
+            confirm_import: None,
+            status: None,
+        };
+        this.refresh();
+        this
+    }
+
+    fn refresh(&mut self) {
+        self.entries.clear();
+        match self.storage {
+            FlashStorage::Files => {
+                let Ok(dirs) = fs::read_dir(FlashStorage::saves_dir(&self.data_dir)) else { return };
+                for entry in dirs.flatten() {
+                    let Some(post_id) = entry.file_name().to_str().and_then(|s| s.parse().ok()) else { continue };
+                    match scan_dir(&entry.path()) {
+                        Ok((modified, size)) => self.entries.push(SaveEntry { post_id, modified: Some(modified), size }),
+                        Err(e) => tracing::warn!("Unable to read saves for post {}: {}", post_id, e),
+                    }
+                }
+            },
+            FlashStorage::Sqlite => {
+                let db_path = FlashStorage::db_path(&self.data_dir);
+                if !db_path.exists() {
+                    return;
+                }
+                match sqlite_storage::list_posts(&db_path) {
+                    Ok(posts) => self.entries.extend(posts.into_iter().map(|(post_id, size)| SaveEntry { post_id, modified: None, size })),
+                    Err(e) => tracing::warn!("Unable to read saves from {}: {}", db_path.display(), e),
+                }
+            },
+        }
+        self.entries.sort_unstable_by(|a, b| b.modified.cmp(&a.modified).then(a.post_id.cmp(&b.post_id)));
+    }
+
+    /// Every shared object saved for `post_id`, as (name, data).
+    fn read_saves(&self, post_id: u32) -> Result<Vec<(String, Vec<u8>)>, String> {
+        match self.storage {
+            FlashStorage::Files => {
+                let disk = DiskStorageBackend::new(FlashStorage::saves_dir(&self.data_dir).join(post_id.to_string()));
+                let names = disk.shared_object_names();
+                Ok(read_all(&disk, names))
+            },
+            FlashStorage::Sqlite => {
+                let db = SqliteStorageBackend::open(&FlashStorage::db_path(&self.data_dir), post_id).map_err(|e| e.to_string())?;
+                let names = db.shared_object_names().map_err(|e| e.to_string())?;
+                Ok(read_all(&db, names))
+            },
+        }
+    }
+
+    /// Zips up the saves of `post_id` as `<post id>/<name>.sol`, the layout `read_import()` expects.
+    fn export(&self, post_id: u32) -> Result<PathBuf, String> {
+        let path = Path::new(&self.export_dir).join(format!("vince621-flash-{}.zip", post_id));
+        let saves = self.read_saves(post_id)?;
+        let write = || -> zip::result::ZipResult<()> {
+            let mut zip = zip::ZipWriter::new(fs::File::create(&path)?);
+            for (name, data) in saves.iter() {
+                zip.start_file(format!("{}/{}.sol", post_id, name), zip::write::FileOptions::default())?;
+                zip.write_all(data)?;
+            }
+            zip.finish()?;
+            Ok(())
+        };
+        write().map_err(|e| e.to_string())?;
+        Ok(path)
+    }
+
+    fn delete(&self, post_id: u32) -> Result<(), String> {
+        match self.storage {
+            FlashStorage::Files => fs::remove_dir_all(FlashStorage::saves_dir(&self.data_dir).join(post_id.to_string())).map_err(|e| e.to_string()),
+            FlashStorage::Sqlite => sqlite_storage::delete_post(&FlashStorage::db_path(&self.data_dir), post_id).map_err(|e| e.to_string()),
+        }
+    }
+
+    /// Writes imported shared objects through the same storage backend the player uses, so they
+    /// get the same name checks and quotas as saves made by the movie itself.  Returns how many
+    /// were written.
+    fn import(&self, objects: &[ImportedObject]) -> usize {
+        let mut post_ids = objects.iter().map(|o| o.post_id).collect::<Vec<_>>();
+        post_ids.sort_unstable();
+        post_ids.dedup();
+        let mut count = 0;
+        for post_id in post_ids {
+            let mut storage = match self.storage.open(&self.data_dir, post_id) {
+                Ok(storage) => storage,
+                Err(e) => {
+                    tracing::warn!("Unable to import saves for post {}: {}", post_id, e);
+                    continue;
+                }
+            };
+            for object in objects.iter().filter(|o| o.post_id == post_id) {
+                if storage.put(&object.name, &object.data) {
+                    count += 1;
+                } else {
+                    tracing::warn!("Unable to import {} for post {}", object.name, object.post_id);
+                }
+            }
+        }
+        count
+    }
+
+    /// Reads a zip to import, and either imports it straight away or, if it has saves for posts
+    /// that already have some, asks first.
+    fn start_import(&mut self) {
+        let objects = match read_import(Path::new(&self.import_path)) {
+            Ok(objects) => objects,
+            Err(e) => {
+                self.status = Some(format!("Import failed: {}", e));
+                return;
+            }
+        };
+        let mut conflicts = objects.iter()
+            .map(|o| o.post_id)
+            .filter(|id| self.entries.iter().any(|e| e.post_id == *id))
+            .collect::<Vec<_>>();
+        conflicts.sort_unstable();
+        conflicts.dedup();
+        if conflicts.is_empty() {
+            self.finish_import(&objects);
+        } else {
+            self.confirm_import = Some((objects, conflicts));
+        }
+    }
+
+    fn finish_import(&mut self, objects: &[ImportedObject]) {
+        let count = self.import(objects);
+        self.status = Some(if count == objects.len() {
+            format!("Imported {} shared objects", count)
+        } else {
+            format!("Imported {} of {} shared objects, see the log for the rest", count, objects.len())
+        });
+        self.refresh();
+    }
+
+    /// Draws the window.  Returns whether it's still open, and the id of a post the user asked
+    /// to open.
+    pub fn show(&mut self, ctx: &egui::Context, posts: &[Post]) -> (bool, Option<u32>) {
+        let mut open = true;
+        let mut open_post = None;
+        egui::Window::new("Flash saves").open(&mut open).show(ctx, |ui| {
+            ui.label(match self.storage {
+                FlashStorage::Files => "Showing saves kept as separate files.  Change where saves go in Settings.",
+                FlashStorage::Sqlite => "Showing saves kept in the SQLite database.  Change where saves go in Settings.",
+            });
+            ui.horizontal(|ui| {
+                ui.label("Export to folder:");
+                ui.text_edit_singleline(&mut self.export_dir);
+            });
+            ui.horizontal(|ui| {
+                ui.label("Import zip:");
+                ui.text_edit_singleline(&mut self.import_path);
+                if ui.add_enabled(self.confirm_import.is_none(), egui::Button::new("Import")).clicked() {
+                    self.start_import();
+                }
+                if ui.button("Refresh").clicked() {
+                    self.refresh();
+                }
+            });
+            let conflicts = self.confirm_import.as_ref().map(|(_, ids)| ids.iter().map(u32::to_string).collect::<Vec<_>>().join(", "));
+            if let Some(ids) = conflicts {
+                ui.horizontal(|ui| {
+                    ui.label(format!("Overwrite saves for posts {}?", ids));
+                    if ui.button("Overwrite").clicked() {
+                        if let Some((objects, _)) = self.confirm_import.take() {
+                            self.finish_import(&objects);
+                        }
+                    }
+                    if ui.button("Cancel").clicked() {
+                        self.confirm_import = None;
+                    }
+                });
+            }
+            if let Some(ref status) = self.status {
+                ui.label(status);
+            }
+            ui.separator();
+            if self.entries.is_empty() {
+                ui.label("No Flash games have saved anything yet.");
+                return;
+            }
+            let mut export = None;
+            let mut delete = None;
+            ScrollArea::vertical().show(ui, |ui| {
+                Grid::new("flash_saves").num_columns(5).striped(true).show(ui, |ui| {
+                    for entry in self.entries.iter() {
+                        match posts.binary_search_by_key(&entry.post_id, |p| p.id.get()) {
+                            Ok(idx) => { ui.add(Image::new(posts[idx].url(ImageResolution::Preview)).max_height(64.0)); },
+                            Err(_) => { ui.label("(not in database)"); },
+                        }
+                        ui.label(format!("Post {}", entry.post_id));
+                        ui.label(entry.modified.map(format_age).unwrap_or_default());
+                        ui.label(format_size(entry.size));
+                        ui.horizontal(|ui| {
+                            if ui.button("Open").clicked() {
+                                open_post = Some(entry.post_id);
+                            }
+                            if ui.button("Export").clicked() {
+                                export = Some(entry.post_id);
+                            }
+                            if self.confirm_delete == Some(entry.post_id) {
+                                if ui.button("Really delete?").clicked() {
+                                    delete = Some(entry.post_id);
+                                }
+                            } else if ui.button("Delete").clicked() {
+                                self.confirm_delete = Some(entry.post_id);
+                            }
+                        });
+                        ui.end_row();
+                    }
+                });
+            });
+            if let Some(post_id) = export {
+                self.status = Some(match self.export(post_id) {
+                    Ok(path) => format!("Exported to {}", path.display()),
+                    Err(e) => format!("Export failed: {}", e),
+                });
+            }
+            if let Some(post_id) = delete {
+                self.confirm_delete = None;
+                if let Err(e) = self.delete(post_id) {
+                    self.status = Some(format!("Delete failed: {}", e));
+                }
+                self.refresh();
+            }
+        });
+        (open, open_post)
+    }
+}
@@ -0,0 +1,71 @@
+use std::ops::Range;
+
+use paste::paste;
+use rand::seq::SliceRandom as _;
+use rayon::{iter::{IndexedParallelIterator as _, IntoParallelRefIterator as _, ParallelIterator as _}, slice::ParallelSliceMut as _};
+use vince621_core::{db::{posts::{FileExtension, Post}, tags::TagAndImplicationDatabase}, search::{e6_posts::{PostKernel, SortOrder}, NestedQuery}};
+
+/// Parses a search query against the tag database.  On failure, returns the byte range of the
+/// offending part of the query along with the reason.
+pub fn parse_query(tag_db: &TagAndImplicationDatabase, query: &str) -> Result<(NestedQuery<PostKernel>, SortOrder), (Range<usize>, String)> {
+    let parse_tag_fn = |s| tag_db.search_wildcard(s).map(|tag| tag.id).collect::<Vec<u32>>();
+    vince621_core::search::e6_posts::parse_query_and_sort_order(parse_tag_fn, query).map_err(|e| {
+        let (start, end) = e.get_range(query);
+        (start..end, e.into_reason())
+    })
+}
+
+/// Sorts search results (indices into `posts`) in place.
+pub fn sort_results(posts: &[Post], results: &mut [usize], sort_order: SortOrder) {
+    match sort_order {
+        SortOrder::DateAscending => {
+            // post database is already sorted by that -- we don't need to do anything
+        },
+        SortOrder::Date => {
+            results.reverse();
+        },
+        SortOrder::Random => {
+            results.shuffle(&mut rand::thread_rng());
+        }
+        other => {
+            macro_rules! match_arms {
+                ($match_on:ident, $results: ident, $posts: ident, $($order: ident => post.$field:ident),*) => {
+                    paste!(
+                    match $match_on {
+                        $(
+                            SortOrder::$order => $results.par_sort_unstable_by(|a,b| $posts[*b].$field.cmp(&$posts[*a].$field)),
+                            SortOrder::[<$order Ascending>] => $results.par_sort_unstable_by(|a,b| $posts[*a].$field.cmp(&$posts[*b].$field)),
+                        )*
+                        SortOrder::Date | SortOrder::DateAscending | SortOrder::Random => unreachable!()
+                    }
+                    )
+                }
+            }
+            match_arms!(other, results, posts, Score => post.score, FavCount => post.fav_count);
+        },
+    }
+}
+
+/// Runs a search over the whole post database without any progress reporting, for when there's
+/// no UI to report it to.
+pub fn search_all(posts: &[Post], query: &NestedQuery<PostKernel>, sort_order: SortOrder) -> Vec<usize> {
+    let mut results = posts.par_iter().enumerate().filter(|(_, post)| query.validate(post)).map(|(idx, _)| idx).collect::<Vec<_>>();
+    sort_results(posts, &mut results, sort_order);
+    results
+}
+
+/// Returns true if any of the blacklist queries match `post`.
+pub fn is_blacklisted(blacklist: &[NestedQuery<PostKernel>], post: &Post) -> bool {
+    blacklist.iter().any(|q| q.validate(post))
+}
+
+/// Position in `results` of the nearest Flash movie after `idx`, or before it if `forward` isn't
+/// set.
+pub fn next_swf(posts: &[Post], results: &[usize], idx: usize, forward: bool) -> Option<usize> {
+    let is_swf = |i: &usize| matches!(posts[results[*i]].file_ext, FileExtension::SWF);
+    if forward {
+        (idx+1..results.len()).find(is_swf)
+    } else {
+        (0..idx.min(results.len())).rev().find(is_swf)
+    }
+}
@@ -0,0 +1,58 @@
+//! Small hand-written databases for the unit tests, in the same CSV format as e621's database
+//! exports.
+
+use std::sync::Arc;
+
+use vince621_core::db::tags::TagAndImplicationDatabase;
+
+use crate::{autocomplete::Autocompleter, usage::TagUsage};
+
+pub fn tag_db_from_csv(tags: &str, aliases: &str, implications: &str) -> Arc<TagAndImplicationDatabase> {
+    Arc::new(vince621_csv::tags::load_tag_and_implication_database(tags.as_bytes(), aliases.as_bytes(), implications.as_bytes()).expect("fixture database should load"))
+}
+
+/// Categories use e621's numbering: 0 general, 1 artist, 3 copyright, 4 character, 5 species,
+/// 7 meta, 8 lore.
+const TAGS: &str = "id,name,category,post_count
+1,wolf,5,50000
+2,wolf_girl,5,3000
+3,wolfram,1,120
+4,fox,5,40000
+5,canine,5,80000
+6,cat,5,60000
+7,café,0,900
+8,ポケモン,3,7000
+9,ポケモン_sv,3,300
+10,naïve_art,0,50
+11,über,0,20
+";
+
+const ALIASES: &str = "id,antecedent_name,consequent_name,created_at,status
+1,wolves,wolf,2020-01-01,active
+2,pokemon,ポケモン,2020-01-01,active
+";
+
+const IMPLICATIONS: &str = "id,antecedent_name,consequent_name,created_at,status
+1,wolf,canine,2020-01-01,active
+2,fox,canine,2020-01-01,active
+";
+
+/// A dozen tags across a few categories, with a mix of ASCII and multi-byte names.
+pub fn tag_db() -> Arc<TagAndImplicationDatabase> {
+    tag_db_from_csv(TAGS, ALIASES, IMPLICATIONS)
+}
+
+/// A different database where the same names have different ids and some tags are gone, like
+/// after downloading a newer export.
+pub fn reloaded_tag_db() -> Arc<TagAndImplicationDatabase> {
+    tag_db_from_csv("id,name,category,post_count
+101,wolf_(character),4,10
+102,fox,5,41000
+", "id,antecedent_name,consequent_name,created_at,status
+", "id,antecedent_name,consequent_name,created_at,status
+")
+}
+
+pub fn autocompleter(tag_db: Arc<TagAndImplicationDatabase>) -> Autocompleter {
+    Autocompleter::new(tag_db, TagUsage::empty())
+}
@@ -0,0 +1,64 @@
+//! Conversions between the byte offsets the query parser works in and the character offsets
+//! egui's text cursors use.  Both directions clamp out-of-range input instead of panicking, since
+//! the offsets we get are often a frame stale relative to the text.
+
+/// Byte offset of the `char_idx`th character of `s`, or `s.len()` if there aren't that many.
+pub fn char_to_byte(s: &str, char_idx: usize) -> usize {
+    s.char_indices().nth(char_idx).map(|x| x.0).unwrap_or(s.len())
+}
+
+/// How many characters of `s` come before `byte_idx`.  An offset in the middle of a character
+/// counts that character as not reached yet.
+pub fn byte_to_char(s: &str, byte_idx: usize) -> usize {
+    let mut byte_idx = byte_idx.min(s.len());
+    while !s.is_char_boundary(byte_idx) {
+        byte_idx -= 1;
+    }
+    s[..byte_idx].chars().count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    // 1, 2, 3 and 4 byte characters
+    const MIXED: &str = "aé漢🦊";
+
+    #[test]
+    fn char_to_byte_multibyte() {
+        let expected = [0, 1, 3, 6, 10];
+        for (char_idx, byte_idx) in expected.into_iter().enumerate() {
+            assert_eq!(char_to_byte(MIXED, char_idx), byte_idx);
+        }
+        assert_eq!(char_to_byte(MIXED, 5), MIXED.len());
+        assert_eq!(char_to_byte(MIXED, usize::MAX), MIXED.len());
+        assert_eq!(char_to_byte("", 0), 0);
+    }
+
+    #[test]
+    fn byte_to_char_multibyte() {
+        let expected = [0, 1, 1, 2, 2, 2, 3, 3, 3, 3, 4];
+        for (byte_idx, char_idx) in expected.into_iter().enumerate() {
+            assert_eq!(byte_to_char(MIXED, byte_idx), char_idx, "byte {}", byte_idx);
+        }
+        assert_eq!(byte_to_char(MIXED, 100), 4);
+        assert_eq!(byte_to_char("", 5), 0);
+    }
+
+    proptest! {
+        #[test]
+        fn char_to_byte_lands_on_boundary(s in "\\PC{0,16}", char_idx in 0usize..24) {
+            let byte_idx = char_to_byte(&s, char_idx);
+            prop_assert!(s.is_char_boundary(byte_idx));
+            prop_assert_eq!(byte_to_char(&s, byte_idx), char_idx.min(s.chars().count()));
+        }
+
+        #[test]
+        fn byte_to_char_never_overshoots(s in "\\PC{0,16}", byte_idx in 0usize..80) {
+            let char_idx = byte_to_char(&s, byte_idx);
+            prop_assert!(char_idx <= s.chars().count());
+            prop_assert!(char_to_byte(&s, char_idx) <= byte_idx.min(s.len()));
+        }
+    }
+}
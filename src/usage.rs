@@ -0,0 +1,65 @@
+use std::{collections::HashMap, path::PathBuf};
+
+/// How often the user has searched for each tag, so the autocompleter can put their usual tags
+/// first.  Stored as a two-column CSV of tag id and count.
+pub struct TagUsage {
+    counts: HashMap<u32, u32>,
+    path: PathBuf,
+}
+
+impl TagUsage {
+    /// Loads usage counts from `path`.  A missing or unreadable file just means starting over.
+    pub fn load(path: PathBuf) -> Self {
+        let mut counts = HashMap::new();
+        match csv::ReaderBuilder::new().has_headers(false).from_path(&path) {
+            Ok(mut reader) => {
+                for record in reader.deserialize::<(u32, u32)>() {
+                    match record {
+                        Ok((id, count)) => { counts.insert(id, count); },
+                        Err(e) => tracing::warn!("Skipping bad line in {}: {}", path.display(), e),
+                    }
+                }
+            },
+            Err(e) => tracing::info!("No tag usage history loaded from {}: {}", path.display(), e),
+        }
+        Self { counts, path }
+    }
+
+    /// No history, and nowhere to save it.  For tests.
+    #[cfg(test)]
+    pub fn empty() -> Self {
+        Self { counts: HashMap::new(), path: PathBuf::new() }
+    }
+
+    pub fn get(&self, tag_id: u32) -> u32 {
+        self.counts.get(&tag_id).copied().unwrap_or(0)
+    }
+
+    /// Bumps the count for each of `tag_ids` and writes the counts back out.
+    pub fn record(&mut self, tag_ids: impl IntoIterator<Item=u32>) {
+        for id in tag_ids {
+            *self.counts.entry(id).or_insert(0) += 1;
+        }
+        if let Err(e) = self.save() {
+            tracing::warn!("Unable to save tag usage to {}: {}", self.path.display(), e);
+        }
+    }
+
+    /// Writes the counts to a temp file and renames it over the real one, so a crash partway
+    /// through can't leave the history empty.
+    fn save(&self) -> Result<(), csv::Error> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut tmp_name = self.path.file_name().unwrap_or_default().to_os_string();
+        tmp_name.push(".tmp");
+        let tmp_path = self.path.with_file_name(tmp_name);
+        let mut writer = csv::WriterBuilder::new().has_headers(false).from_path(&tmp_path)?;
+        for (id, count) in self.counts.iter() {
+            writer.serialize((id, count))?;
+        }
+        writer.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        std::fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+}